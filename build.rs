@@ -0,0 +1,550 @@
+// Generates typed bindings for the Wayland protocols in protocols/
+// The output is included by src/wayland/protocol.rs
+
+use std::{env, fmt::Write as _, fs, path::Path};
+
+const PROTOCOLS: &[&str] = &[
+    "wayland.xml",
+    "xdg-shell.xml",
+    "wlr-layer-shell-unstable-v1.xml",
+    "xdg-output-unstable-v1.xml",
+    "fractional-scale-v1.xml",
+    "viewporter.xml",
+];
+
+const KEYWORDS: &[&str] = &[
+    "as", "break", "const", "continue", "else", "enum", "extern", "false", "fn", "for", "if",
+    "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "static",
+    "struct", "trait", "true", "type", "unsafe", "use", "where", "while", "async", "await", "dyn",
+    "abstract", "become", "box", "do", "final", "macro", "override", "priv", "typeof", "unsized",
+    "virtual", "yield", "try", "gen",
+];
+
+// ---- Minimal XML parsing ----
+
+struct Element {
+    name:       String,
+    attrs:      Vec<(String, String)>,
+    children:   Vec<Element>,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+}
+
+fn unescape(str: &str) -> String {
+    str.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Parse the element starting at xml[*pos] (which must be '<')
+/// Text content is skipped, only the element tree is kept
+fn parse_element(xml: &str, pos: &mut usize) -> Element {
+    let tag_end = *pos + xml[*pos..].find('>').expect("unterminated tag");
+    let tag = &xml[*pos+1..tag_end];
+    let self_closing = tag.ends_with('/');
+    let tag = tag.trim_end_matches('/');
+    *pos = tag_end + 1;
+
+    let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+    let mut element = Element {
+        name:       tag[..name_end].to_string(),
+        attrs:      Vec::new(),
+        children:   Vec::new(),
+    };
+
+    let mut rest = &tag[name_end..];
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim().to_string();
+        let quote_start = eq + rest[eq..].find('"').expect("unquoted attribute");
+        let quote_end = quote_start + 1 + rest[quote_start+1..].find('"').expect("unterminated attribute");
+        element.attrs.push((key, unescape(&rest[quote_start+1..quote_end])));
+        rest = &rest[quote_end+1..];
+    }
+
+    if self_closing {
+        return element;
+    }
+
+    loop {
+        let next = *pos + xml[*pos..].find('<').expect("unterminated element");
+        *pos = next;
+        if xml[next..].starts_with("<!--") {
+            *pos = next + xml[next..].find("-->").expect("unterminated comment") + 3;
+        }
+        else if xml[next..].starts_with("</") {
+            *pos = next + xml[next..].find('>').expect("unterminated closing tag") + 1;
+            return element;
+        }
+        else {
+            element.children.push(parse_element(xml, pos));
+        }
+    }
+}
+
+fn parse_document(xml: &str) -> Element {
+    let mut pos = 0;
+    loop {
+        pos += xml[pos..].find('<').expect("no root element");
+        if xml[pos..].starts_with("<?") {
+            pos += xml[pos..].find("?>").expect("unterminated declaration") + 2;
+        }
+        else if xml[pos..].starts_with("<!--") {
+            pos += xml[pos..].find("-->").expect("unterminated comment") + 3;
+        }
+        else {
+            return parse_element(xml, &mut pos);
+        }
+    }
+}
+
+// ---- Code generation ----
+
+fn camel_case(snake: &str) -> String {
+    let mut res = String::new();
+    let mut upper = true;
+    for ch in snake.chars() {
+        if ch == '_' {
+            upper = true;
+        } else if upper {
+            res.push(ch.to_ascii_uppercase());
+            upper = false;
+        } else {
+            res.push(ch);
+        }
+    }
+    if res.starts_with(|ch: char| ch.is_ascii_digit()) {
+        res.insert(0, '_');
+    }
+    res
+}
+
+fn field_name(name: &str) -> String {
+    if KEYWORDS.contains(&name) {
+        format!("r#{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn summary(element: &Element) -> Option<String> {
+    element.attr("summary")
+        .or_else(|| element.children("description").next().and_then(|desc| desc.attr("summary")))
+        .map(|summary| summary.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+fn write_doc(out: &mut String, indent: &str, element: &Element) {
+    if let Some(summary) = summary(element) {
+        writeln!(out, "{}/// {}", indent, summary).unwrap();
+    }
+}
+
+struct Arg {
+    name:       String,
+    kind:       String,
+    interface:  Option<String>,
+    nullable:   bool,
+}
+
+impl Arg {
+    fn new(element: &Element) -> Arg {
+        Arg {
+            name:       element.attr("name").expect("arg without name").to_string(),
+            kind:       element.attr("type").expect("arg without type").to_string(),
+            interface:  element.attr("interface").map(str::to_string),
+            nullable:   element.attr("allow-null") == Some("true"),
+        }
+    }
+
+    /// new_id without an interface is sent as (interface, version, id)
+    fn is_untyped_new_id(&self) -> bool {
+        self.kind == "new_id" && self.interface.is_none()
+    }
+
+    fn rust_type(&self) -> &'static str {
+        match self.kind.as_str() {
+            "int"       => "i32",
+            "uint"      => "u32",
+            "fixed"     => "Fixed",
+            "string" if self.nullable => "Option<String>",
+            "string"    => "String",
+            "object"    => "u32",
+            "new_id"    => "u32",
+            "array"     => "Vec<u8>",
+            "fd"        => "OwnedFd",
+            kind        => panic!("unknown arg type {}", kind),
+        }
+    }
+
+    fn arg_kind(&self) -> &'static str {
+        match self.kind.as_str() {
+            "int"       => "ArgKind::Int",
+            "uint"      => "ArgKind::Uint",
+            "fixed"     => "ArgKind::Fixed",
+            "string"    => "ArgKind::String",
+            "object"    => "ArgKind::Object",
+            "new_id"    => "ArgKind::NewId",
+            "array"     => "ArgKind::Array",
+            "fd"        => "ArgKind::Fd",
+            kind        => panic!("unknown arg type {}", kind),
+        }
+    }
+}
+
+struct Message {
+    name:       String,
+    destructor: bool,
+    doc:        Option<String>,
+    args:       Vec<Arg>,
+}
+
+impl Message {
+    fn new(element: &Element) -> Message {
+        Message {
+            name:       element.attr("name").expect("message without name").to_string(),
+            destructor: element.attr("type") == Some("destructor"),
+            doc:        summary(element),
+            args:       element.children("arg").map(Arg::new).collect(),
+        }
+    }
+
+    fn variant(&self) -> String {
+        camel_case(&self.name)
+    }
+
+    fn fields(&self) -> String {
+        self.args.iter()
+            .map(|arg| field_name(&arg.name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn pattern(&self, enum_name: &str) -> String {
+        if self.args.is_empty() {
+            format!("{}::{}", enum_name, self.variant())
+        } else {
+            format!("{}::{} {{ {} }}", enum_name, self.variant(), self.fields())
+        }
+    }
+}
+
+fn gen_descriptors(out: &mut String, messages: &[Message]) {
+    for message in messages {
+        writeln!(out, "        MessageDesc {{").unwrap();
+        writeln!(out, "            name: \"{}\",", message.name).unwrap();
+        writeln!(out, "            destructor: {},", message.destructor).unwrap();
+        writeln!(out, "            args: &[").unwrap();
+        for arg in &message.args {
+            writeln!(
+                out,
+                "                ArgDesc {{ kind: {}, interface: {:?} }},",
+                arg.arg_kind(),
+                arg.interface,
+            ).unwrap();
+        }
+        writeln!(out, "            ],").unwrap();
+        writeln!(out, "        }},").unwrap();
+    }
+}
+
+fn gen_message_enum(out: &mut String, enum_name: &str, messages: &[Message]) {
+    writeln!(out, "    #[derive(Debug)]").unwrap();
+    writeln!(out, "    pub enum {} {{", enum_name).unwrap();
+    for message in messages {
+        if let Some(doc) = &message.doc {
+            writeln!(out, "        /// {}", doc).unwrap();
+        }
+        if message.args.is_empty() {
+            writeln!(out, "        {},", message.variant()).unwrap();
+            continue;
+        }
+        writeln!(out, "        {} {{", message.variant()).unwrap();
+        for arg in &message.args {
+            if arg.is_untyped_new_id() {
+                writeln!(out, "            interface: String,").unwrap();
+                writeln!(out, "            version: u32,").unwrap();
+            }
+            writeln!(out, "            {}: {},", field_name(&arg.name), arg.rust_type()).unwrap();
+        }
+        writeln!(out, "        }},").unwrap();
+    }
+    writeln!(out, "    }}\n").unwrap();
+
+    writeln!(out, "    impl Message for {} {{", enum_name).unwrap();
+    writeln!(out, "        const INTERFACE: &'static Interface = &INTERFACE;\n").unwrap();
+
+    // opcode
+    writeln!(out, "        fn opcode(&self) -> u16 {{").unwrap();
+    if messages.is_empty() {
+        writeln!(out, "            match *self {{}}").unwrap();
+    } else {
+        writeln!(out, "            match self {{").unwrap();
+        for (opcode, message) in messages.iter().enumerate() {
+            let pattern = if message.args.is_empty() { "" } else { " { .. }" };
+            writeln!(out, "                {}::{}{} => {},", enum_name, message.variant(), pattern, opcode).unwrap();
+        }
+        writeln!(out, "            }}").unwrap();
+    }
+    writeln!(out, "        }}\n").unwrap();
+
+    // encode
+    writeln!(out, "        fn encode(self, object: u32, fds: &mut Vec<OwnedFd>) -> Vec<u8> {{").unwrap();
+    if messages.is_empty() {
+        writeln!(out, "            match self {{}}").unwrap();
+    } else {
+        writeln!(out, "            let opcode = self.opcode();").unwrap();
+        writeln!(out, "            match self {{").unwrap();
+        for message in messages {
+            let mut pattern = message.pattern(enum_name);
+            if message.args.iter().any(Arg::is_untyped_new_id) {
+                pattern = pattern.replace(" { ", " { interface, version, ");
+            }
+            writeln!(out, "                {} => {{", pattern).unwrap();
+            let mut size = vec!["8".to_string()];
+            for arg in &message.args {
+                let name = field_name(&arg.name);
+                if arg.is_untyped_new_id() {
+                    size.push("string_size(&interface)".to_string());
+                    size.push("4".to_string());
+                }
+                size.push(match arg.kind.as_str() {
+                    "string" if arg.nullable => format!("{}.as_deref().map_or(4, string_size)", name),
+                    "string"    => format!("string_size(&{})", name),
+                    "array"     => format!("array_size(&{})", name),
+                    "fd"        => continue,
+                    _           => "4".to_string(),
+                });
+            }
+            writeln!(out, "                    let msg_size: usize = {};", size.join(" + ")).unwrap();
+            writeln!(out, "                    let mut msg = vec![0u8; msg_size];").unwrap();
+            writeln!(out, "                    let mut msg_offset: usize = 0;").unwrap();
+            writeln!(out, "                    msg.write_u32(&object, &mut msg_offset);").unwrap();
+            writeln!(out, "                    msg.write_u16(&opcode, &mut msg_offset);").unwrap();
+            writeln!(out, "                    msg.write_u16(&(msg_size as u16), &mut msg_offset);").unwrap();
+            for arg in &message.args {
+                let name = field_name(&arg.name);
+                if arg.is_untyped_new_id() {
+                    writeln!(out, "                    msg.write_string(&interface, &mut msg_offset);").unwrap();
+                    writeln!(out, "                    msg.write_u32(&version, &mut msg_offset);").unwrap();
+                }
+                match arg.kind.as_str() {
+                    "int"       => writeln!(out, "                    msg.write_i32(&{}, &mut msg_offset);", name),
+                    "fixed"     => writeln!(out, "                    msg.write_i32(&{}.0, &mut msg_offset);", name),
+                    "string" if arg.nullable
+                                => writeln!(out, "                    msg.write_opt_string({}.as_deref(), &mut msg_offset);", name),
+                    "string"    => writeln!(out, "                    msg.write_string(&{}, &mut msg_offset);", name),
+                    "array"     => writeln!(out, "                    msg.write_array(&{}, &mut msg_offset);", name),
+                    "fd"        => writeln!(out, "                    fds.push({});", name),
+                    _           => writeln!(out, "                    msg.write_u32(&{}, &mut msg_offset);", name),
+                }.unwrap();
+            }
+            writeln!(out, "                    msg").unwrap();
+            writeln!(out, "                }}").unwrap();
+        }
+        writeln!(out, "            }}").unwrap();
+    }
+    writeln!(out, "        }}\n").unwrap();
+
+    // decode
+    writeln!(
+        out,
+        "        fn decode(opcode: u16, body: &[u8], fds: &mut VecDeque<OwnedFd>) -> Result<Self, DecodeErr> {{"
+    ).unwrap();
+    writeln!(out, "            let mut msg_offset: usize = 0;").unwrap();
+    writeln!(out, "            match opcode {{").unwrap();
+    for (opcode, message) in messages.iter().enumerate() {
+        if message.args.is_empty() {
+            writeln!(out, "                {} => Ok({}::{}),", opcode, enum_name, message.variant()).unwrap();
+            continue;
+        }
+        writeln!(out, "                {} => Ok({}::{} {{", opcode, enum_name, message.variant()).unwrap();
+        for arg in &message.args {
+            if arg.is_untyped_new_id() {
+                writeln!(out, "                    interface: body.read_string(&mut msg_offset)?,").unwrap();
+                writeln!(out, "                    version: body.read_u32(&mut msg_offset)?,").unwrap();
+            }
+            let read = match arg.kind.as_str() {
+                "int"       => "body.read_i32(&mut msg_offset)?",
+                "fixed"     => "Fixed(body.read_i32(&mut msg_offset)?)",
+                "string" if arg.nullable
+                            => "body.read_opt_string(&mut msg_offset)?",
+                "string"    => "body.read_string(&mut msg_offset)?",
+                "array"     => "body.read_array(&mut msg_offset)?",
+                "fd"        => "take_fd(fds)?",
+                _           => "body.read_u32(&mut msg_offset)?",
+            };
+            writeln!(out, "                    {}: {},", field_name(&arg.name), read).unwrap();
+        }
+        writeln!(out, "                }}),").unwrap();
+    }
+    writeln!(
+        out,
+        "                _ => Err(DecodeErr(format!(\"invalid opcode {{}} for {{}}\", opcode, NAME))),"
+    ).unwrap();
+    writeln!(out, "            }}").unwrap();
+    writeln!(out, "        }}").unwrap();
+    writeln!(out, "    }}\n").unwrap();
+}
+
+fn gen_enum(out: &mut String, element: &Element) {
+    let name = camel_case(element.attr("name").expect("enum without name"));
+    let mut entries: Vec<(String, u32, &Element)> = Vec::new();
+    for entry in element.children("entry") {
+        let value = entry.attr("value").expect("entry without value");
+        let value = match value.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => value.parse(),
+        }.expect("invalid entry value");
+        // Aliases would be duplicate discriminants, keep the first name
+        if entries.iter().any(|(_, other, _)| *other == value) {
+            continue;
+        }
+        entries.push((camel_case(entry.attr("name").expect("entry without name")), value, entry));
+    }
+
+    write_doc(out, "    ", element);
+    writeln!(out, "    #[derive(Debug, Clone, Copy, PartialEq, Eq)]").unwrap();
+    writeln!(out, "    #[repr(u32)]").unwrap();
+    writeln!(out, "    pub enum {} {{", name).unwrap();
+    for (variant, value, entry) in &entries {
+        write_doc(out, "        ", entry);
+        writeln!(out, "        {} = {},", variant, value).unwrap();
+    }
+    writeln!(out, "    }}\n").unwrap();
+
+    writeln!(out, "    impl TryFrom<u32> for {} {{", name).unwrap();
+    writeln!(out, "        type Error = u32;").unwrap();
+    writeln!(out, "        fn try_from(value: u32) -> Result<Self, u32> {{").unwrap();
+    writeln!(out, "            match value {{").unwrap();
+    for (variant, value, _) in &entries {
+        writeln!(out, "                {} => Ok({}::{}),", value, name, variant).unwrap();
+    }
+    writeln!(out, "                _ => Err(value),").unwrap();
+    writeln!(out, "            }}").unwrap();
+    writeln!(out, "        }}").unwrap();
+    writeln!(out, "    }}\n").unwrap();
+}
+
+/// Returns whether the interface has an error enum
+fn gen_interface(out: &mut String, element: &Element) -> bool {
+    let name = element.attr("name").expect("interface without name");
+    let version = element.attr("version").expect("interface without version");
+    let requests: Vec<Message> = element.children("request").map(Message::new).collect();
+    let events: Vec<Message> = element.children("event").map(Message::new).collect();
+
+    write_doc(out, "", element);
+    writeln!(out, "pub mod {} {{", name).unwrap();
+    writeln!(out, "    use super::*;\n").unwrap();
+    writeln!(out, "    pub const NAME: &str = \"{}\";", name).unwrap();
+    writeln!(out, "    pub const VERSION: u32 = {};\n", version).unwrap();
+
+    writeln!(out, "    pub static INTERFACE: Interface = Interface {{").unwrap();
+    writeln!(out, "        name: NAME,").unwrap();
+    writeln!(out, "        version: VERSION,").unwrap();
+    writeln!(out, "        requests: &REQUESTS,").unwrap();
+    writeln!(out, "        events: &EVENTS,").unwrap();
+    writeln!(out, "    }};\n").unwrap();
+
+    writeln!(out, "    static REQUESTS: [MessageDesc; {}] = [", requests.len()).unwrap();
+    gen_descriptors(out, &requests);
+    writeln!(out, "    ];\n").unwrap();
+    writeln!(out, "    static EVENTS: [MessageDesc; {}] = [", events.len()).unwrap();
+    gen_descriptors(out, &events);
+    writeln!(out, "    ];\n").unwrap();
+
+    gen_message_enum(out, "Request", &requests);
+    gen_message_enum(out, "Event", &events);
+
+    let mut has_error = false;
+    for enum_element in element.children("enum") {
+        gen_enum(out, enum_element);
+        has_error |= enum_element.attr("name") == Some("error");
+    }
+
+    writeln!(out, "}}\n").unwrap();
+    has_error
+}
+
+/// wl_display.error codes resolved to the error enum of the interface that raised them
+fn gen_error_code(out: &mut String, interfaces: &[String]) {
+    writeln!(out, "#[derive(Debug, Clone, Copy, PartialEq, Eq)]").unwrap();
+    writeln!(out, "pub enum ErrorCode {{").unwrap();
+    for interface in interfaces {
+        writeln!(out, "    {}({}::Error),", camel_case(interface), interface).unwrap();
+    }
+    writeln!(out, "    Unknown(u32),").unwrap();
+    writeln!(out, "}}\n").unwrap();
+
+    writeln!(out, "impl ErrorCode {{").unwrap();
+    writeln!(out, "    pub fn new(interface: &str, code: u32) -> Self {{").unwrap();
+    writeln!(out, "        match interface {{").unwrap();
+    for interface in interfaces {
+        writeln!(
+            out,
+            "            {}::NAME => {}::Error::try_from(code).map_or(ErrorCode::Unknown(code), ErrorCode::{}),",
+            interface, interface, camel_case(interface)
+        ).unwrap();
+    }
+    writeln!(out, "            _ => ErrorCode::Unknown(code),").unwrap();
+    writeln!(out, "        }}").unwrap();
+    writeln!(out, "    }}\n").unwrap();
+
+    writeln!(out, "    pub fn code(self) -> u32 {{").unwrap();
+    writeln!(out, "        match self {{").unwrap();
+    for interface in interfaces {
+        writeln!(out, "            ErrorCode::{}(error) => error as u32,", camel_case(interface)).unwrap();
+    }
+    writeln!(out, "            ErrorCode::Unknown(code) => code,").unwrap();
+    writeln!(out, "        }}").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}\n").unwrap();
+
+    writeln!(out, "impl std::fmt::Display for ErrorCode {{").unwrap();
+    writeln!(out, "    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {{").unwrap();
+    writeln!(out, "        match self {{").unwrap();
+    for interface in interfaces {
+        writeln!(out, "            ErrorCode::{}(error) => write!(f, \"{}::{{:?}}\", error),", camel_case(interface), interface).unwrap();
+    }
+    writeln!(out, "            ErrorCode::Unknown(code) => write!(f, \"unknown error {{}}\", code),").unwrap();
+    writeln!(out, "        }}").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}").unwrap();
+}
+
+fn main() {
+    let mut out = String::new();
+    let mut interfaces = Vec::new();
+    let mut error_interfaces = Vec::new();
+    for protocol in PROTOCOLS {
+        let path = Path::new("protocols").join(protocol);
+        println!("cargo:rerun-if-changed={}", path.display());
+        let xml = fs::read_to_string(&path).expect("failed to read protocol");
+        let root = parse_document(&xml);
+        writeln!(out, "// Generated from {}\n", protocol).unwrap();
+        for interface in root.children("interface") {
+            interfaces.push(interface.attr("name").unwrap().to_string());
+            if gen_interface(&mut out, interface) {
+                error_interfaces.push(interface.attr("name").unwrap().to_string());
+            }
+        }
+    }
+    gen_error_code(&mut out, &error_interfaces);
+
+    writeln!(out, "\npub static INTERFACES: [&Interface; {}] = [", interfaces.len()).unwrap();
+    for interface in &interfaces {
+        writeln!(out, "    &{}::INTERFACE,", interface).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+
+    let out_path = Path::new(&env::var("OUT_DIR").unwrap()).join("protocols.rs");
+    fs::write(out_path, out).expect("failed to write bindings");
+}
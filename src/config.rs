@@ -0,0 +1,204 @@
+use std::{error::Error, fmt::Display, path::PathBuf};
+
+use crate::{graphics::drawable::premultiply, wayland::protocol::{zwlr_layer_shell_v1::Layer, zwlr_layer_surface_v1::{Anchor, KeyboardInteractivity}}};
+
+pub const USAGE: &str = "\
+Usage: chlorostart [options]
+
+Options:
+    --trace                 Log every request and event like WAYLAND_DEBUG=1
+    --layer <layer>         background, bottom, top or overlay (default)
+    --anchor <edges>        Comma separated top, bottom, left and right, none centers
+    --margin <px>           One margin for every edge or top,right,bottom,left
+    --exclusive-zone <px>   Space reserved for the launcher, -1 covers other panels
+    --namespace <name>      Layer namespace, or app id without layer shell
+    --size <w>x<h>          Requested size, 0 stretches between opposite anchors
+    --keyboard <mode>       none, exclusive (default) or on-demand focus
+    --output <output>       focused (default), all or an output name like DP-1
+    --background <color>    rrggbb or aarrggbb, transparent by default
+    --deep-color            10 bit XRGB2101010 buffers for an opaque background, colors stay 8 bit
+    --screenshot <file>     Render the launcher to a PNG file without a compositor
+    -h, --help              Show this help";
+
+#[derive(Debug)]
+pub struct ConfigErr(pub String);
+
+impl Display for ConfigErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\n\n{}", self.0, USAGE)
+    }
+}
+
+impl Error for ConfigErr {}
+
+/// Size asked for when none is given
+pub const DEFAULT_WIDTH: u32 = 800;
+pub const DEFAULT_HEIGHT: u32 = 800;
+
+/// Which monitors the launcher opens on
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum OutputChoice {
+    /// Left to the compositor, usually the output last interacted with
+    #[default]
+    Focused,
+    Named(String),
+    /// One launcher surface per output, following hotplug
+    All,
+}
+
+/// Where the layer surface goes, see zwlr_layer_surface_v1
+#[derive(Debug, Clone)]
+pub struct Placement {
+    pub layer:          Layer,
+    /// Anchor bits, no edge centers the surface
+    pub anchor:         u32,
+    /// Top, right, bottom, left
+    pub margin:         [i32; 4],
+    pub exclusive_zone: i32,
+    pub namespace:      String,
+    pub width:          u32,
+    pub height:         u32,
+    pub keyboard:       KeyboardInteractivity,
+    pub output:         OutputChoice,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Placement {
+            layer:          Layer::Overlay,
+            anchor:         0,
+            margin:         [0; 4],
+            exclusive_zone: 0,
+            namespace:      "chlorostart".to_string(),
+            width:          DEFAULT_WIDTH,
+            height:         DEFAULT_HEIGHT,
+            keyboard:       KeyboardInteractivity::Exclusive,
+            output:         OutputChoice::Focused,
+        }
+    }
+}
+
+pub fn parse_layer(name: &str) -> Option<Layer> {
+    match name {
+        "background" => Some(Layer::Background),
+        "bottom" => Some(Layer::Bottom),
+        "top" => Some(Layer::Top),
+        "overlay" => Some(Layer::Overlay),
+        _ => None,
+    }
+}
+
+fn parse_keyboard(mode: &str) -> Option<KeyboardInteractivity> {
+    match mode {
+        "none" => Some(KeyboardInteractivity::None),
+        "exclusive" => Some(KeyboardInteractivity::Exclusive),
+        "on-demand" => Some(KeyboardInteractivity::OnDemand),
+        _ => None,
+    }
+}
+
+fn parse_anchor(edges: &str) -> Result<u32, ConfigErr> {
+    if edges == "none" {
+        return Ok(0);
+    }
+    edges.split(',').try_fold(0, |anchor, edge| Ok(anchor | match edge {
+        "top" => Anchor::Top,
+        "bottom" => Anchor::Bottom,
+        "left" => Anchor::Left,
+        "right" => Anchor::Right,
+        _ => return Err(ConfigErr(format!("Unknown anchor \"{}\"", edge))),
+    } as u32))
+}
+
+fn parse_margin(margin: &str) -> Result<[i32; 4], ConfigErr> {
+    let err = || ConfigErr(format!("Invalid margin \"{}\"", margin));
+    let values = margin.split(',')
+        .map(|value| value.parse().map_err(|_| err()))
+        .collect::<Result<Vec<i32>, ConfigErr>>()?;
+    match values[..] {
+        [all] => Ok([all; 4]),
+        [top, right, bottom, left] => Ok([top, right, bottom, left]),
+        _ => Err(err()),
+    }
+}
+
+/// Hex color with an optional alpha, premultiplied
+fn parse_color(color: &str) -> Result<u32, ConfigErr> {
+    let err = || ConfigErr(format!("Invalid color \"{}\"", color));
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    let value = u32::from_str_radix(hex, 16).map_err(|_| err())?;
+    match hex.len() {
+        6 => Ok(0xff000000 | value),
+        8 => Ok(premultiply(value)),
+        _ => Err(err()),
+    }
+}
+
+fn parse_size(size: &str) -> Result<(u32, u32), ConfigErr> {
+    size.split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .ok_or(ConfigErr(format!("Invalid size \"{}\"", size)))
+}
+
+#[derive(Debug, Default)]
+pub struct Config {
+    pub trace:      bool,
+    pub help:       bool,
+    pub screenshot: Option<PathBuf>,
+    pub placement:  Placement,
+    /// Premultiplied, an opaque one lets buffers go without alpha
+    pub background: u32,
+    /// Pick the 10 bit XRGB2101010 format, colors are still only 8 bit precise
+    pub deep_color: bool,
+}
+
+impl Config {
+    /// Parse the command line, without the program name
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, ConfigErr> {
+        let mut config = Config::default();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(ConfigErr(format!("{} needs a value", arg)));
+            let placement = &mut config.placement;
+            match arg.as_str() {
+                "--trace" => config.trace = true,
+                "--screenshot" => config.screenshot = Some(PathBuf::from(value()?)),
+                "--layer" => {
+                    let layer = value()?;
+                    placement.layer = parse_layer(&layer).ok_or(ConfigErr(format!("Unknown layer \"{}\"", layer)))?;
+                }
+                "--anchor" => placement.anchor = parse_anchor(&value()?)?,
+                "--margin" => placement.margin = parse_margin(&value()?)?,
+                "--exclusive-zone" => {
+                    let zone = value()?;
+                    placement.exclusive_zone = zone.parse().map_err(|_| ConfigErr(format!("Invalid exclusive zone \"{}\"", zone)))?;
+                }
+                "--namespace" => placement.namespace = value()?,
+                "--size" => (placement.width, placement.height) = parse_size(&value()?)?,
+                "--keyboard" => {
+                    let mode = value()?;
+                    placement.keyboard = parse_keyboard(&mode).ok_or(ConfigErr(format!("Unknown keyboard mode \"{}\"", mode)))?;
+                }
+                "--output" => placement.output = match value()?.as_str() {
+                    "focused" => OutputChoice::Focused,
+                    "all" => OutputChoice::All,
+                    name => OutputChoice::Named(name.to_string()),
+                },
+                "--background" => config.background = parse_color(&value()?)?,
+                "--deep-color" => config.deep_color = true,
+                "-h" | "--help" => config.help = true,
+                _ => return Err(ConfigErr(format!("Unknown argument \"{}\"", arg))),
+            }
+        }
+
+        // Layer shell only accepts a zero size between two opposite anchors
+        let placement = &config.placement;
+        let both = |a: Anchor, b: Anchor| placement.anchor & (a as u32 | b as u32) == a as u32 | b as u32;
+        if placement.width == 0 && !both(Anchor::Left, Anchor::Right) {
+            return Err(ConfigErr("A width of 0 needs --anchor left,right".to_string()));
+        }
+        if placement.height == 0 && !both(Anchor::Top, Anchor::Bottom) {
+            return Err(ConfigErr("A height of 0 needs --anchor top,bottom".to_string()));
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,171 @@
+use std::{error::Error, fmt, fs, io, path::Path};
+
+use super::{damage::Rect, drawable::color_over, png};
+
+#[derive(Debug)]
+pub struct OutOfBoundsErr (pub String);
+
+impl Error for OutOfBoundsErr {}
+impl fmt::Display for OutOfBoundsErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is out of bounds!", self.0)
+    }
+}
+
+/// Pixel target drawables render into
+///
+/// Pixels are premultiplied ARGB8888 and addressed by column and row from the top left
+/// corner. Width and height are in pixels, drawables lay out in logical units and
+/// multiply by scale. The drawing methods clip to the canvas, so shapes may reach past
+/// any edge
+pub trait Canvas {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+
+    /// Pixels per logical unit
+    fn scale(&self) -> f64 {
+        1.0
+    }
+
+    fn get(&self, x: usize, y: usize) -> Result<u32, OutOfBoundsErr>;
+    /// Replace the pixel at x, y, ignoring what was there
+    fn set(&mut self, x: usize, y: usize, color: u32) -> Result<(), OutOfBoundsErr>;
+
+    /// Part of the run of len pixels from x, y that may be drawn, as x, y and len
+    fn clip_row(&self, x: isize, y: isize, len: usize) -> Option<(usize, usize, usize)> {
+        if y < 0 || y as usize >= self.height() {
+            return None;
+        }
+        let start = x.max(0);
+        let end = x.saturating_add_unsigned(len).min(self.width() as isize);
+        (start < end).then(|| (start as usize, y as usize, (end - start) as usize))
+    }
+
+    /// Blend color over the pixel at x, y
+    fn blend_pixel(&mut self, x: isize, y: isize, color: u32) {
+        self.blend_row(x, y, 1, color);
+    }
+
+    /// Blend color over len pixels of row y from x
+    fn blend_row(&mut self, x: isize, y: isize, len: usize, color: u32) {
+        let Some((x, y, len)) = self.clip_row(x, y, len) else {
+            return;
+        };
+        for x in x..x + len {
+            // Clipped already, neither can fail
+            if let Ok(under) = self.get(x, y) {
+                let _ = self.set(x, y, color_over(color, under));
+            }
+        }
+    }
+
+    /// Set len pixels of row y from x to color
+    fn fill_row(&mut self, x: isize, y: isize, len: usize, color: u32) {
+        let Some((x, y, len)) = self.clip_row(x, y, len) else {
+            return;
+        };
+        for x in x..x + len {
+            let _ = self.set(x, y, color);
+        }
+    }
+
+    fn clear(&mut self, color: u32) {
+        for y in 0..self.height() {
+            self.fill_row(0, y as isize, self.width(), color);
+        }
+    }
+}
+
+/// Canvas that drops every write outside clip, for redrawing part of another canvas
+pub struct ClipCanvas<'a> {
+    pub canvas: &'a mut dyn Canvas,
+    pub clip:   Rect,
+}
+
+impl Canvas for ClipCanvas<'_> {
+    fn width(&self) -> usize {
+        self.canvas.width()
+    }
+
+    fn height(&self) -> usize {
+        self.canvas.height()
+    }
+
+    fn scale(&self) -> f64 {
+        self.canvas.scale()
+    }
+
+    fn get(&self, x: usize, y: usize) -> Result<u32, OutOfBoundsErr> {
+        self.canvas.get(x, y)
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: u32) -> Result<(), OutOfBoundsErr> {
+        if self.clip.intersect(&Rect::new(x, y, 1, 1)).is_none() {
+            return Err(OutOfBoundsErr(format!("Pixel {},{} outside the clip", x, y)));
+        }
+        self.canvas.set(x, y, color)
+    }
+
+    fn clip_row(&self, x: isize, y: isize, len: usize) -> Option<(usize, usize, usize)> {
+        let (x, y, len) = self.canvas.clip_row(x, y, len)?;
+        let row = self.clip.intersect(&Rect::new(x, y, len, 1))?;
+        Some((row.x, row.y, row.width))
+    }
+}
+
+/// Offscreen canvas in plain memory, for rendering without a compositor
+#[derive(Debug, Clone)]
+pub struct MemoryCanvas {
+    pub width:  usize,
+    pub height: usize,
+    pub scale:  f64,
+    pub pixels: Vec<u32>,
+}
+
+impl MemoryCanvas {
+    pub fn new(width: usize, height: usize) -> Self {
+        MemoryCanvas {
+            width,
+            height,
+            scale:  1.0,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        png::encode(self.width, self.height, &self.pixels)
+    }
+
+    pub fn save_png(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_png())
+    }
+}
+
+impl Canvas for MemoryCanvas {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    fn get(&self, x: usize, y: usize) -> Result<u32, OutOfBoundsErr> {
+        if x >= self.width || y >= self.height {
+            return Err(OutOfBoundsErr(format!("Pixel {},{}", x, y)));
+        }
+        Ok(self.pixels[y * self.width + x])
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: u32) -> Result<(), OutOfBoundsErr> {
+        if x >= self.width || y >= self.height {
+            return Err(OutOfBoundsErr(format!("Pixel {},{}", x, y)));
+        }
+        self.pixels[y * self.width + x] = color;
+        Ok(())
+    }
+}
@@ -1,5 +1,4 @@
-use crate::wayland::{shm::ShmPool, wl_shm::wl_buffer};
-use super::drawable::{Drawable, color_blend};
+use super::{canvas::Canvas, damage::Rect, drawable::{Centered, Drawable, color_blend, scaled}};
 
 // x and y are center of circle
 pub struct Circle {
@@ -7,42 +6,70 @@ pub struct Circle {
     y: usize,
     radius: usize,
     color: u32,
+    block: Option<Centered>,
 }
 
 impl Circle {
     pub fn new(x: usize, y: usize, radius: usize, color: u32) -> Self {
-        Circle { x, y, radius, color }
+        Circle { x, y, radius, color, block: None }
+    }
+
+    /// Place in a centered block, x and y are then from its top left corner
+    pub fn centered(mut self, block: Centered) -> Self {
+        self.block = Some(block);
+        self
+    }
+
+    /// Center on the surface
+    fn position(&self) -> (usize, usize) {
+        let (x, y) = self.block.map_or((0, 0), |block| block.origin);
+        (x + self.x, y + self.y)
     }
 }
 
 impl Drawable for Circle {
-    fn update(&mut self) {
+    fn update(&mut self) -> bool {
         // self.x += 1;
+        false
+    }
+
+    fn layout(&mut self, width: usize, height: usize) {
+        if let Some(block) = &mut self.block {
+            block.layout(width, height);
+        }
+    }
+
+    fn bounds(&self) -> Rect {
+        let (x, y) = self.position();
+        Rect::new(x.saturating_sub(self.radius), y.saturating_sub(self.radius), 2 * self.radius, 2 * self.radius)
     }
 
-    fn draw(&self, buffer: &wl_buffer, shm_pool: &mut ShmPool) {
-        for l_row in 1..self.radius { 
-            let inner_diff = (((self.radius-1).pow(2) - l_row.pow(2)) as f64).sqrt();
-            let outer_diff = ((self.radius.pow(2) - l_row.pow(2)) as f64).sqrt();
-            shm_pool.write(self.color, (self.y-l_row)*buffer.width + self.x - inner_diff.floor() as usize + buffer.offset, 2*(inner_diff.floor() as usize));
-            shm_pool.write(self.color, (self.y+l_row-1)*buffer.width + self.x - inner_diff.floor() as usize + buffer.offset, 2*(inner_diff.floor() as usize));
-            for l_col in (inner_diff.floor() as usize+1)..(outer_diff.ceil() as usize) {
-                let distance = ((l_row.pow(2) + l_col.pow(2)) as f64).sqrt();
-                let offset = (self.y-l_row)*buffer.width + self.x - l_col + buffer.offset;
-                shm_pool.write_pixel(color_blend(self.color, 0, distance.fract()), offset);
-                let offset = (self.y-l_row)*buffer.width + self.x + l_col-1 + buffer.offset;
-                shm_pool.write_pixel(color_blend(self.color, 0, distance.fract()), offset);
-                let offset = (self.y+l_row-1)*buffer.width + self.x - l_col + buffer.offset;
-                shm_pool.write_pixel(color_blend(self.color, 0, distance.fract()), offset);
-                let offset = (self.y+l_row-1)*buffer.width + self.x + l_col-1 + buffer.offset;
-                shm_pool.write_pixel(color_blend(self.color, 0, distance.fract()), offset);
+    fn draw(&self, canvas: &mut dyn Canvas) {
+        let scale = canvas.scale();
+        let (x, y) = self.position();
+        let (x, y, radius) = (scaled(x, scale) as isize, scaled(y, scale) as isize, scaled(self.radius, scale));
+        for l_row in 1..radius {
+            let inner_diff = (((radius-1).pow(2) - l_row.pow(2)) as f64).sqrt();
+            let outer_diff = ((radius.pow(2) - l_row.pow(2)) as f64).sqrt();
+            let inner = inner_diff.floor() as usize;
+            let l_row = l_row as isize;
+            canvas.blend_row(x - inner as isize, y-l_row, 2*inner, self.color);
+            canvas.blend_row(x - inner as isize, y+l_row-1, 2*inner, self.color);
+            for l_col in (inner+1)..(outer_diff.ceil() as usize) {
+                let distance = ((l_row.pow(2) as usize + l_col.pow(2)) as f64).sqrt();
+                let color = color_blend(self.color, 0, distance.fract());
+                let l_col = l_col as isize;
+                canvas.blend_pixel(x - l_col, y-l_row, color);
+                canvas.blend_pixel(x + l_col-1, y-l_row, color);
+                canvas.blend_pixel(x - l_col, y+l_row-1, color);
+                canvas.blend_pixel(x + l_col-1, y+l_row-1, color);
             }
         }
     }
 }
 
-impl Into<Box<dyn Drawable>> for Circle {
-    fn into(self) -> Box<dyn Drawable> {
-        Box::new(self)
+impl From<Circle> for Box<dyn Drawable> {
+    fn from(value: Circle) -> Self {
+        Box::new(value)
     }
 }
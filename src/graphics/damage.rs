@@ -0,0 +1,85 @@
+use super::drawable::scaled;
+
+/// Damage lists longer than this are merged into their bounding box
+const MAX_RECTS: usize = 8;
+
+/// Axis aligned rectangle, x and y are the top left corner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x:      usize,
+    pub y:      usize,
+    pub width:  usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Rect { x, y, width, height }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Smallest rectangle holding both
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        Rect::new(x, y, right - x, bottom - y)
+    }
+
+    /// True if the point x, y lies inside
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.x as f64 && y >= self.y as f64
+            && x < (self.x + self.width) as f64 && y < (self.y + self.height) as f64
+    }
+
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        (right > x && bottom > y).then(|| Rect::new(x, y, right - x, bottom - y))
+    }
+
+    /// Logical rectangle to the pixels it touches at scale, a pixel wider on every
+    /// side for what rounding in the drawables may add
+    pub fn scaled(&self, scale: f64) -> Rect {
+        let (x, y) = (scaled(self.x, scale).saturating_sub(1), scaled(self.y, scale).saturating_sub(1));
+        let right = scaled(self.x + self.width, scale) + 1;
+        let bottom = scaled(self.y + self.height, scale) + 1;
+        Rect::new(x, y, right - x, bottom - y)
+    }
+
+    /// Pixel rectangle to the logical one covering it
+    pub fn unscaled(&self, scale: f64) -> Rect {
+        let (x, y) = ((self.x as f64 / scale) as usize, (self.y as f64 / scale) as usize);
+        let right = ((self.x + self.width) as f64 / scale).ceil() as usize;
+        let bottom = ((self.y + self.height) as f64 / scale).ceil() as usize;
+        Rect::new(x, y, right - x, bottom - y)
+    }
+}
+
+/// Add rect to a damage list, merging it with the rectangles it overlaps
+pub fn add_damage(damage: &mut Vec<Rect>, rect: Rect) {
+    if rect.is_empty() {
+        return;
+    }
+    let mut rect = rect;
+    // A merged rectangle can overlap ones checked before it, so go again until none does
+    while let Some(index) = damage.iter().position(|other| other.intersect(&rect).is_some()) {
+        rect = rect.union(&damage.swap_remove(index));
+    }
+    damage.push(rect);
+    if damage.len() > MAX_RECTS {
+        let bounds = damage.iter().fold(Rect::default(), |bounds, rect| bounds.union(rect));
+        damage.clear();
+        damage.push(bounds);
+    }
+}
@@ -1,30 +1,30 @@
-use crate::wayland::{shm::ShmPool, wl_shm::wl_buffer};
+use super::{canvas::Canvas, damage::Rect};
 
 pub fn premultiply(color: u32) -> u32 {
     let a = (color & 0xff000000) >> 24;
     let r = (color & 0x00ff0000) >> 16;
     let g = (color & 0x0000ff00) >> 8;
-    let b = (color & 0x000000ff) >> 0;
-    let alpha = (a as f64 / 0xff as f64);
+    let b = color & 0x000000ff;
+    let alpha = a as f64 / 0xff as f64;
     (a << 24) +
     (((r as f64*alpha) as u32) << 16) +
     (((g as f64*alpha) as u32) << 8) +
-    (((b as f64*alpha) as u32) << 0)
+    ((b as f64*alpha) as u32)
 }
 
 pub fn color_blend(col1: u32, col2: u32, diff: f64) -> u32 {
     let a1 = (col1 & 0xff000000) >> 24;
     let r1 = (col1 & 0x00ff0000) >> 16;
     let g1 = (col1 & 0x0000ff00) >> 8;
-    let b1 = (col1 & 0x000000ff) >> 0;
+    let b1 = col1 & 0x000000ff;
     let a2 = (col2 & 0xff000000) >> 24;
     let r2 = (col2 & 0x00ff0000) >> 16;
     let g2 = (col2 & 0x0000ff00) >> 8;
-    let b2 = (col2 & 0x000000ff) >> 0;
+    let b2 = col2 & 0x000000ff;
     (((a1 as f64 + (a2 as f64 - a1 as f64) * diff).round() as u32) << 24) +
     (((r1 as f64 + (r2 as f64 - r1 as f64) * diff).round() as u32) << 16) +
     (((g1 as f64 + (b2 as f64 - g1 as f64) * diff).round() as u32) << 8 ) +
-    (((b1 as f64 + (g2 as f64 - b1 as f64) * diff).round() as u32) << 0 )
+    ((b1 as f64 + (g2 as f64 - b1 as f64) * diff).round() as u32)
 }
 
 pub fn color_over(over: u32, under: u32) -> u32 {
@@ -40,15 +40,62 @@ pub fn color_over(over: u32, under: u32) -> u32 {
     let r_under = (under & 0x00ff0000) >> 16;
     let g_over  = (over  & 0x0000ff00) >> 8;
     let g_under = (under & 0x0000ff00) >> 8;
-    let b_over  = (over  & 0x000000ff) >> 0;
-    let b_under = (under & 0x000000ff) >> 0;
+    let b_over  = over  & 0x000000ff;
+    let b_under = under & 0x000000ff;
     ((a_over + (a_under as f64 * (0xff - a_over) as f64 / 0xff as f64) as u32).min(0xff) << 24) +
     ((r_over + (r_under as f64 * (0xff - a_over) as f64 / 0xff as f64) as u32).min(0xff) << 16) +
     ((g_over + (g_under as f64 * (0xff - a_over) as f64 / 0xff as f64) as u32).min(0xff) << 8) +
-    ((b_over + (b_under as f64 * (0xff - a_over) as f64 / 0xff as f64) as u32).min(0xff) << 0)
+    ((b_over + (b_under as f64 * (0xff - a_over) as f64 / 0xff as f64) as u32).min(0xff))
 }
 
+/// Logical length in pixels at scale
+pub fn scaled(length: usize, scale: f64) -> usize {
+    (length as f64 * scale).round() as usize
+}
+
+/// Block of fixed logical size kept centered on the surface, shapes placed in one are
+/// positioned from its top left corner
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Centered {
+    pub width:  usize,
+    pub height: usize,
+    /// Top left corner on the surface, never pushed past the surface's own
+    pub origin: (usize, usize),
+}
+
+impl Centered {
+    pub fn new(width: usize, height: usize) -> Self {
+        Centered { width, height, origin: (0, 0) }
+    }
+
+    pub fn layout(&mut self, width: usize, height: usize) {
+        self.origin = (width.saturating_sub(self.width) / 2, height.saturating_sub(self.height) / 2);
+    }
+}
+
+/// Positions and sizes are logical, draw scales them by the canvas
 pub trait Drawable : Send {
-    fn update(&mut self);
-    fn draw(&self, buffer: &wl_buffer, shm_pool: &mut ShmPool);
+    /// Advance to the next frame, true if the drawable looks different now
+    fn update(&mut self) -> bool;
+    /// True while update keeps changing the drawable, frames are only drawn on demand otherwise
+    fn animating(&self) -> bool {
+        false
+    }
+    /// Lay out again for a surface of logical width and height, called on every resize
+    fn layout(&mut self, _width: usize, _height: usize) {}
+    /// Logical area draw writes to, also where the pointer hits the drawable
+    fn bounds(&self) -> Rect;
+    /// The pointer moved onto (true) or off the drawable, true if it looks different now
+    fn hover(&mut self, _hovered: bool) -> bool {
+        false
+    }
+    /// Clicked with the primary button, returns a command to launch
+    fn click(&mut self) -> Option<String> {
+        None
+    }
+    /// Scrolled by logical dx, dy while under the pointer, true if it looks different now
+    fn scroll(&mut self, _dx: f64, _dy: f64) -> bool {
+        false
+    }
+    fn draw(&self, canvas: &mut dyn Canvas);
 }
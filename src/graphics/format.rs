@@ -0,0 +1,39 @@
+/// How a 32 bit pixel is laid out in a buffer, drawables always work in premultiplied ARGB8888
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PixelFormat {
+    #[default]
+    Argb8888,
+    /// Alpha is ignored, compositors can skip blending the surface
+    Xrgb8888,
+    /// 10 bits per channel and alpha ignored, though drawables only fill the top 8 of them
+    Xrgb2101010,
+}
+
+impl PixelFormat {
+    /// Premultiplied ARGB8888 color as the format stores it
+    pub fn encode(&self, color: u32) -> u32 {
+        match self {
+            PixelFormat::Argb8888 => color,
+            PixelFormat::Xrgb8888 => color | 0xff000000,
+            PixelFormat::Xrgb2101010 => {
+                // The top bits repeat in the new low ones so 0xff becomes 0x3ff
+                let channel = |shift: u32| {
+                    let value = (color >> shift) & 0xff;
+                    (value << 2) | (value >> 6)
+                };
+                0xc0000000 | channel(16) << 20 | channel(8) << 10 | channel(0)
+            }
+        }
+    }
+
+    /// Stored pixel back to premultiplied ARGB8888
+    pub fn decode(&self, pixel: u32) -> u32 {
+        match self {
+            PixelFormat::Argb8888 => pixel,
+            PixelFormat::Xrgb8888 => pixel | 0xff000000,
+            PixelFormat::Xrgb2101010 => {
+                0xff000000 | ((pixel >> 22) & 0xff) << 16 | ((pixel >> 12) & 0xff) << 8 | ((pixel >> 2) & 0xff)
+            }
+        }
+    }
+}
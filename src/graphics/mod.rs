@@ -2,3 +2,11 @@
 pub mod drawable;
 pub mod rectangle;
 pub mod circle;
+pub mod canvas;
+pub mod damage;
+pub mod format;
+pub mod png;
+pub mod renderer;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,95 @@
+// Minimal PNG encoder: 8 bit RGBA, no filtering, stored (uncompressed) deflate blocks
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+// Largest length a stored deflate block can hold
+const MAX_STORED: usize = 0xffff;
+
+fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+        }
+        *entry = c;
+    }
+    table
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let table = crc32_table();
+    !data.iter().fold(!0u32, |crc, byte| table[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), byte| {
+        let a = (a + *byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    (b << 16) | a
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// zlib stream of data in stored blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut zlib = Vec::with_capacity(data.len() + data.len() / MAX_STORED * 5 + 11);
+    // Deflate with a 32K window, no preset dictionary, check bits make it a multiple of 31
+    zlib.extend_from_slice(&[0x78, 0x01]);
+    let mut blocks = data.chunks(MAX_STORED).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        zlib.push(blocks.peek().is_none() as u8);
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+    zlib
+}
+
+/// Premultiplied ARGB8888 to straight RGBA
+fn unpremultiply(color: u32) -> [u8; 4] {
+    let a = color >> 24;
+    if a == 0 {
+        return [0, 0, 0, 0];
+    }
+    let channel = |shift: u32| ((((color >> shift) & 0xff) * 0xff + a / 2) / a).min(0xff) as u8;
+    [channel(16), channel(8), channel(0), a as u8]
+}
+
+/// Encode premultiplied ARGB8888 pixels, row by row from the top left
+pub fn encode(width: usize, height: usize, pixels: &[u32]) -> Vec<u8> {
+    assert_eq!(pixels.len(), width * height, "pixel count doesn't match the size");
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth 8, color type 6 (RGBA), deflate, adaptive filtering, no interlace
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut raw = Vec::with_capacity((width * 4 + 1) * height);
+    for row in pixels.chunks(width.max(1)) {
+        // Filter type none
+        raw.push(0);
+        for pixel in row {
+            raw.extend_from_slice(&unpremultiply(*pixel));
+        }
+    }
+
+    let mut png = Vec::new();
+    png.extend_from_slice(&SIGNATURE);
+    write_chunk(&mut png, b"IHDR", &ihdr);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
@@ -1,54 +1,95 @@
-use crate::wayland::{shm::ShmPool, wl_shm::wl_buffer};
-use super::drawable::{Drawable, color_blend};
+use super::{canvas::Canvas, damage::Rect, drawable::{Centered, Drawable, color_blend, color_over, scaled}};
+
+/// Premultiplied white blended over hovered rectangles
+const HIGHLIGHT: u32 = 0x33333333;
 
 // x and y and topleft corner of rect
 pub struct Rectangle {
-    x:      usize,
-    y:      usize,
-    width:  usize,
-    height: usize,
-    radius: usize,
-    color:  u32,
+    x:          usize,
+    y:          usize,
+    width:      usize,
+    height:     usize,
+    radius:     usize,
+    color:      u32,
+    hovered:    bool,
+    block:      Option<Centered>,
 }
 
 impl Rectangle {
     pub fn new(x: usize, y: usize, width: usize, height: usize, radius: usize, color: u32) -> Self {
-        Rectangle {x, y, width, height, radius, color}
+        Rectangle {x, y, width, height, radius, color, hovered: false, block: None}
+    }
+
+    /// Place in a centered block, x and y are then from its top left corner
+    pub fn centered(mut self, block: Centered) -> Self {
+        self.block = Some(block);
+        self
+    }
+
+    /// Top left corner on the surface
+    fn position(&self) -> (usize, usize) {
+        let (x, y) = self.block.map_or((0, 0), |block| block.origin);
+        (x + self.x, y + self.y)
     }
 }
 
 impl Drawable for Rectangle {
-    fn update(&mut self) {
+    fn update(&mut self) -> bool {
         // self.x += 1;
+        false
+    }
+
+    fn layout(&mut self, width: usize, height: usize) {
+        if let Some(block) = &mut self.block {
+            block.layout(width, height);
+        }
+    }
+
+    fn bounds(&self) -> Rect {
+        let (x, y) = self.position();
+        Rect::new(x, y, self.width, self.height)
+    }
+
+    fn hover(&mut self, hovered: bool) -> bool {
+        let changed = self.hovered != hovered;
+        self.hovered = hovered;
+        changed
     }
 
-    fn draw(&self, buffer: &wl_buffer, shm_pool: &mut ShmPool) {
-        for g_row in self.y+self.radius..self.y+self.height-self.radius+1 {
-            shm_pool.write(self.color, g_row*buffer.width as usize+self.x + buffer.offset, self.width);
+    fn draw(&self, canvas: &mut dyn Canvas) {
+        let color = if self.hovered { color_over(HIGHLIGHT, self.color) } else { self.color };
+        let scale = canvas.scale();
+        let (x, y) = self.position();
+        let (x, y) = (scaled(x, scale) as isize, scaled(y, scale) as isize);
+        let (w, h) = (scaled(self.width, scale), scaled(self.height, scale));
+        let radius = scaled(self.radius, scale);
+        let (r, wi, hi) = (radius as isize, w as isize, h as isize);
+        for g_row in y+r..y+hi-r+1 {
+            canvas.blend_row(x, g_row, w, color);
         }
-        for l_row in 1..self.radius {
-            let inner_diff = (((self.radius-1).pow(2) - l_row.pow(2)) as f64).sqrt();
-            let outer_diff = ((self.radius.pow(2) - l_row.pow(2)) as f64).sqrt();
-            shm_pool.write(self.color, (self.y+self.radius-l_row)*buffer.width as usize + self.x + self.radius - inner_diff.floor() as usize-1 + buffer.offset, self.width - (2*(self.radius - inner_diff.floor() as usize-1)));
-            shm_pool.write(self.color, (self.y+self.height-self.radius+l_row)*buffer.width as usize + self.x + self.radius - inner_diff.floor() as usize-1 + buffer.offset, self.width - (2*(self.radius - inner_diff.floor() as usize-1)));
+        for l_row in 1..radius {
+            let inner_diff = (((radius-1).pow(2) - l_row.pow(2)) as f64).sqrt();
+            let outer_diff = ((radius.pow(2) - l_row.pow(2)) as f64).sqrt();
+            let l_row = l_row as isize;
+            // Columns of the corner curve left of the straight part
+            let curve = radius - inner_diff.floor() as usize - 1;
+            canvas.blend_row(x + curve as isize, y+r-l_row, w.saturating_sub(2*curve), color);
+            canvas.blend_row(x + curve as isize, y+hi-r+l_row, w.saturating_sub(2*curve), color);
             for l_col in inner_diff.floor() as usize+1..outer_diff.ceil() as usize {
-                // TODO: handle error from read_pixel
-                let distance = ((l_row.pow(2) + l_col.pow(2)) as f64).sqrt();
-                let offset = (self.y+self.radius-l_row)*buffer.width as usize + self.x + self.radius - l_col - 1 + buffer.offset;
-                shm_pool.write_pixel(color_blend(self.color, 0, distance.fract()), offset);
-                let offset = (self.y+self.radius-l_row)*buffer.width as usize + self.x + self.width - self.radius + l_col + buffer.offset;
-                shm_pool.write_pixel(color_blend(self.color, 0, distance.fract()), offset);
-                let offset = (self.y+self.height-self.radius+l_row)*buffer.width as usize + self.x + self.radius - l_col - 1 + buffer.offset;
-                shm_pool.write_pixel(color_blend(self.color, 0, distance.fract()), offset);
-                let offset = (self.y+self.height-self.radius+l_row)*buffer.width as usize + self.x + self.width - self.radius + l_col + buffer.offset;
-                shm_pool.write_pixel(color_blend(self.color, 0, distance.fract()), offset);
+                let distance = ((l_row.pow(2) as usize + l_col.pow(2)) as f64).sqrt();
+                let color = color_blend(color, 0, distance.fract());
+                let l_col = l_col as isize;
+                canvas.blend_pixel(x + r - l_col - 1, y+r-l_row, color);
+                canvas.blend_pixel(x + wi - r + l_col, y+r-l_row, color);
+                canvas.blend_pixel(x + r - l_col - 1, y+hi-r+l_row, color);
+                canvas.blend_pixel(x + wi - r + l_col, y+hi-r+l_row, color);
             }
         }
     }
 }
 
-impl Into<Box<dyn Drawable>> for Rectangle {
-    fn into(self) -> Box<dyn Drawable> {
-        Box::new(self)
+impl From<Rectangle> for Box<dyn Drawable> {
+    fn from(value: Rectangle) -> Self {
+        Box::new(value)
     }
 }
@@ -0,0 +1,68 @@
+use std::{io, path::Path};
+
+use super::{canvas::{Canvas, ClipCanvas, MemoryCanvas}, circle::Circle, damage::{add_damage, Rect}, drawable::{premultiply, Centered, Drawable}, rectangle::Rectangle};
+
+/// Demo shapes laid out for an 800x800 surface, kept centered on other sizes
+pub fn demo_scene(width: usize, height: usize) -> Vec<Box<dyn Drawable>> {
+    let block = Centered::new(700, 700);
+    let mut scene: Vec<Box<dyn Drawable>> = vec![
+        Rectangle::new(0, 0, 300, 300, 16, 0xffff8800).centered(block).into(),
+        Rectangle::new(300, 0, 300, 300, 16, premultiply(0x77aa22aa)).centered(block).into(),
+        Circle::new(300, 30, 25, premultiply(0xff00ffff)).centered(block).into(),
+        Circle::new(300, 110, 25, premultiply(0x8800ffff)).centered(block).into(),
+        Circle::new(300, 190, 25, premultiply(0x0000ffff)).centered(block).into(),
+    ];
+    for drawable in &mut scene {
+        drawable.layout(width, height);
+    }
+    scene
+}
+
+/// Draw the next frame of drawables onto a canvas cleared to background
+pub fn render(drawables: &mut [Box<dyn Drawable>], canvas: &mut dyn Canvas, background: u32) {
+    canvas.clear(background);
+    for drawable in drawables {
+        drawable.update();
+        drawable.draw(canvas);
+    }
+}
+
+/// Advance the drawables to the next frame, returns the logical areas that changed
+pub fn update(drawables: &mut [Box<dyn Drawable>]) -> Vec<Rect> {
+    let mut damage = Vec::new();
+    for drawable in drawables {
+        // A drawable that moved leaves damage where it was too
+        let before = drawable.bounds();
+        if drawable.update() {
+            add_damage(&mut damage, before);
+            add_damage(&mut damage, drawable.bounds());
+        }
+    }
+    damage
+}
+
+/// Clear to background and draw again only the pixels inside region
+pub fn redraw(drawables: &[Box<dyn Drawable>], canvas: &mut dyn Canvas, region: &[Rect], background: u32) {
+    let (width, scale) = (canvas.width(), canvas.scale());
+    let bounds = Rect::new(0, 0, width, canvas.height());
+    for rect in region.iter().filter_map(|rect| rect.intersect(&bounds)) {
+        let mut canvas = ClipCanvas { canvas: &mut *canvas, clip: rect };
+        for row in rect.y..rect.y + rect.height {
+            canvas.fill_row(rect.x as isize, row as isize, rect.width, background);
+        }
+        for drawable in drawables.iter().filter(|drawable| drawable.bounds().scaled(scale).intersect(&rect).is_some()) {
+            drawable.draw(&mut canvas);
+        }
+    }
+}
+
+/// Render the scene offscreen, no compositor needed
+pub fn render_offscreen(drawables: &mut [Box<dyn Drawable>], width: usize, height: usize, background: u32) -> MemoryCanvas {
+    let mut canvas = MemoryCanvas::new(width, height);
+    render(drawables, &mut canvas, background);
+    canvas
+}
+
+pub fn screenshot(path: &Path, width: usize, height: usize, background: u32) -> io::Result<()> {
+    render_offscreen(&mut demo_scene(width, height), width, height, background).save_png(path)
+}
@@ -0,0 +1,146 @@
+use crate::graphics::{canvas::{Canvas, MemoryCanvas}, circle::Circle, drawable::Drawable, rectangle::Rectangle, damage::{add_damage, Rect}, format::PixelFormat, png::{adler32, crc32, encode}, renderer::{demo_scene, redraw, render, render_offscreen}};
+
+/// Chunks of a PNG as (type, data), checking their CRCs on the way
+fn chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    let mut chunks = Vec::new();
+    let mut offset = 8;
+    while offset < png.len() {
+        let len = u32::from_be_bytes(png[offset..offset + 4].try_into().unwrap()) as usize;
+        let kind: [u8; 4] = png[offset + 4..offset + 8].try_into().unwrap();
+        let data = png[offset + 8..offset + 8 + len].to_vec();
+        let crc = u32::from_be_bytes(png[offset + 8 + len..offset + 12 + len].try_into().unwrap());
+        assert_eq!(crc, crc32(&png[offset + 4..offset + 8 + len]));
+        chunks.push((kind, data));
+        offset += 12 + len;
+    }
+    chunks
+}
+
+/// Undo the stored deflate blocks of a zlib stream
+fn inflate_stored(zlib: &[u8]) -> Vec<u8> {
+    assert_eq!((zlib[0] as u16 * 256 + zlib[1] as u16) % 31, 0);
+    let mut data = Vec::new();
+    let mut offset = 2;
+    loop {
+        let last = zlib[offset] & 1 == 1;
+        assert_eq!(zlib[offset] >> 1, 0, "only stored blocks are expected");
+        let len = u16::from_le_bytes([zlib[offset + 1], zlib[offset + 2]]) as usize;
+        let nlen = u16::from_le_bytes([zlib[offset + 3], zlib[offset + 4]]);
+        assert_eq!(!nlen as usize, len);
+        data.extend_from_slice(&zlib[offset + 5..offset + 5 + len]);
+        offset += 5 + len;
+        if last {
+            break;
+        }
+    }
+    assert_eq!(u32::from_be_bytes(zlib[offset..offset + 4].try_into().unwrap()), adler32(&data));
+    data
+}
+
+#[test]
+fn checksums_match_reference_values() {
+    assert_eq!(crc32(b"IEND"), 0xae426082);
+    assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+}
+
+#[test]
+fn png_holds_unpremultiplied_rgba_rows() {
+    let png = encode(2, 2, &[0xffff0000, 0x80400000, 0x00000000, 0xff0000ff]);
+    let chunks = chunks(&png);
+    let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+    assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+    assert_eq!(chunks[0].1, [0, 0, 0, 2, 0, 0, 0, 2, 8, 6, 0, 0, 0]);
+    assert_eq!(inflate_stored(&chunks[1].1), [
+        0, 0xff, 0, 0, 0xff, 0x80, 0, 0, 0x80,
+        0, 0, 0, 0, 0, 0, 0, 0xff, 0xff,
+    ]);
+}
+
+#[test]
+fn png_splits_large_images_into_blocks() {
+    let png = encode(200, 200, &vec![0xff102030; 200 * 200]);
+    let chunks = chunks(&png);
+    let raw = inflate_stored(&chunks[1].1);
+    assert_eq!(raw.len(), (200 * 4 + 1) * 200);
+    assert_eq!(&raw[1..5], [0x10, 0x20, 0x30, 0xff]);
+}
+
+#[test]
+fn memory_canvas_clips_rows_to_its_edges() {
+    let mut canvas = MemoryCanvas::new(4, 4);
+    canvas.blend_row(2, 3, 10, 0xffffffff);
+    assert_eq!(canvas.pixels[14..], [0xffffffff, 0xffffffff]);
+    // Rows don't wrap around into the next one, nothing above or left is touched
+    canvas.blend_row(-2, 0, 3, 0xff0000ff);
+    canvas.fill_row(0, -1, 4, 0xffffffff);
+    assert_eq!(canvas.pixels[..5], [0xff0000ff, 0, 0, 0, 0]);
+    assert!(canvas.get(4, 0).is_err());
+    assert!(canvas.set(0, 4, 0).is_err());
+}
+
+#[test]
+fn shapes_past_the_edges_are_clipped() {
+    let mut canvas = MemoryCanvas::new(100, 100);
+    // Reaching past the top left and the bottom right corners
+    let mut drawables: [Box<dyn Drawable>; 2] = [
+        Rectangle::new(80, 80, 60, 60, 10, 0xffff8800).into(),
+        Circle::new(5, 5, 25, 0xff0000ff).into(),
+    ];
+    render(&mut drawables, &mut canvas, 0);
+    assert_eq!(canvas.pixels[0], 0xff0000ff);
+    assert_eq!(canvas.pixels[99 * 100 + 99], 0xffff8800);
+    assert_eq!(canvas.pixels[50 * 100 + 50], 0);
+}
+
+#[test]
+fn offscreen_render_matches_the_scene() {
+    let canvas = render_offscreen(&mut demo_scene(800, 800), 800, 800, 0);
+    assert_eq!(canvas.pixels[10 * 800 + 10], 0);
+    assert_eq!(canvas.pixels[100 * 800 + 100], 0xffff8800);
+    // Drawables later in the scene end up on top
+    assert_eq!(canvas.pixels[100 * 800 + 349], 0xff00ffff);
+}
+
+#[test]
+fn scaled_canvas_keeps_logical_layout() {
+    let mut canvas = MemoryCanvas::new(1000, 1000);
+    canvas.scale = 1.25;
+    render(&mut demo_scene(800, 800), &mut canvas, 0);
+    // The rectangle's left edge moves from 50 to 62.5, rounded to 63
+    assert_eq!(canvas.pixels[200 * 1000 + 62], 0);
+    assert_eq!(canvas.pixels[200 * 1000 + 63], 0xffff8800);
+}
+
+#[test]
+fn overlapping_damage_is_merged() {
+    let mut damage = Vec::new();
+    add_damage(&mut damage, Rect::new(0, 0, 10, 10));
+    add_damage(&mut damage, Rect::new(20, 0, 10, 10));
+    assert_eq!(damage.len(), 2);
+    // Bridges both, so all three become one
+    add_damage(&mut damage, Rect::new(5, 5, 20, 2));
+    assert_eq!(damage, [Rect::new(0, 0, 30, 10)]);
+}
+
+#[test]
+fn redraw_leaves_pixels_outside_the_region() {
+    let mut canvas = MemoryCanvas::new(800, 800);
+    canvas.clear(0xff123456);
+    redraw(&demo_scene(800, 800), &mut canvas, &[Rect::new(0, 0, 100, 100)], 0);
+    assert_eq!(canvas.pixels[10 * 800 + 10], 0);
+    assert_eq!(canvas.pixels[80 * 800 + 80], 0xffff8800);
+    assert_eq!(canvas.pixels[80 * 800 + 100], 0xff123456);
+    assert_eq!(canvas.pixels[100 * 800 + 80], 0xff123456);
+}
+
+#[test]
+fn ten_bit_pixels_keep_eight_bit_colors() {
+    let format = PixelFormat::Xrgb2101010;
+    assert_eq!(format.encode(0xffff8000), 0xc0000000 | 0x3ff << 20 | 0x202 << 10);
+    for color in [0xff000000, 0xffffffff, 0xff123456, 0xff80c0e0] {
+        assert_eq!(format.decode(format.encode(color)), color);
+    }
+    // Alpha is ignored, reading back always gives an opaque color
+    assert_eq!(PixelFormat::Xrgb8888.decode(PixelFormat::Xrgb8888.encode(0x00123456)), 0xff123456);
+}
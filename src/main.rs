@@ -1,14 +1,43 @@
 #![feature(unix_socket_ancillary_data)]
-#![feature(str_from_raw_parts)]
 
-use std::{error::Error, thread, time::Duration};
+use std::{env::args, process::ExitCode};
 
 mod wayland;
 use wayland::wl_client::WlClient;
 mod graphics;
+use graphics::renderer::screenshot;
+mod config;
+use config::{Config, DEFAULT_HEIGHT, DEFAULT_WIDTH, USAGE};
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let mut wl_client = WlClient::run()?;
+fn main() -> ExitCode {
+    let config = match Config::from_args(args().skip(1)) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    if config.help {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
 
-    Ok(())
+    if let Some(path) = &config.screenshot {
+        // A stretched size is only known once a compositor configures it
+        let placement = &config.placement;
+        let width = if placement.width == 0 { DEFAULT_WIDTH } else { placement.width };
+        let height = if placement.height == 0 { DEFAULT_HEIGHT } else { placement.height };
+        if let Err(err) = screenshot(path, width as usize, height as usize, config.background) {
+            eprintln!("Failed to write {}: {}", path.display(), err);
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    if let Err(err) = WlClient::run(config) {
+        eprintln!("{}", err);
+        return ExitCode::FAILURE;
+    }
+
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,207 @@
+use std::{collections::VecDeque, io::{self, IoSlice, IoSliceMut, Write}, os::{fd::{AsRawFd, FromRawFd, OwnedFd, RawFd}, unix::net::{AncillaryData, SocketAncillary, UnixStream}}};
+
+use libc::{poll, pollfd, POLLOUT};
+
+use crate::wayland::vec_utils::WlMessage;
+
+const READ_CHUNK: usize = 4096;
+// Room for the 28 fds libwayland sends at most per sendmsg
+const ANCILLARY_SIZE: usize = 256;
+const MAX_FDS_OUT: usize = 28;
+// Queued requests past this are written right away, waiting for the compositor if needed
+const MAX_BUFFERED: usize = 4096 * 4;
+
+pub struct WlHeader {
+    pub object: u32,
+    pub opcode: u16,
+    pub size:   u16,
+}
+
+/// Complete event as read from the socket, not decoded yet
+pub struct RawEvent {
+    pub header: WlHeader,
+    pub body:   Vec<u8>,
+    pub fds:    VecDeque<OwnedFd>,
+}
+
+/// Buffered wayland socket
+///
+/// Incoming bytes and fds are queued separately in the order they arrive, messages
+/// are only handed out once they are complete and take fds from the front of the queue.
+/// Outgoing requests are queued until flushed, their fds go out with the first write
+/// that follows them so they never arrive after the message they belong to
+pub struct Connection {
+    socket:     UnixStream,
+    in_data:    Vec<u8>,
+    in_fds:     VecDeque<OwnedFd>,
+    out_data:   Vec<u8>,
+    out_fds:    VecDeque<OwnedFd>,
+}
+
+impl Connection {
+    pub fn new(socket: UnixStream) -> Self {
+        Connection {
+            socket,
+            in_data:    Vec::new(),
+            in_fds:     VecDeque::new(),
+            out_data:   Vec::new(),
+            out_fds:    VecDeque::new(),
+        }
+    }
+
+    /// Read everything currently available on the socket
+    /// Returns the number of bytes read, an error of kind WouldBlock if there was nothing
+    pub fn read(&mut self) -> io::Result<usize> {
+        let mut total = 0;
+        loop {
+            let mut chunk = [0u8; READ_CHUNK];
+            let mut ancillary_buf = [0u8; ANCILLARY_SIZE];
+            let mut ancillary = SocketAncillary::new(&mut ancillary_buf);
+
+            let read = match self.socket.recv_vectored_with_ancillary(
+                &mut [IoSliceMut::new(&mut chunk)],
+                &mut ancillary
+            ) {
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock && total > 0 => return Ok(total),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+
+            for ancillary_result in ancillary.messages() {
+                if let Ok(AncillaryData::ScmRights(scm_rights)) = ancillary_result {
+                    self.in_fds.extend(scm_rights.map(|received| unsafe { OwnedFd::from_raw_fd(received) }));
+                }
+            }
+            if ancillary.truncated() {
+                return Err(io::Error::other("Ancillary data truncated, file descriptors were lost"));
+            }
+            if read == 0 {
+                // Hand out what came before, it may be the error that got us disconnected
+                if total > 0 {
+                    return Ok(total);
+                }
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Compositor closed the connection"));
+            }
+
+            self.in_data.extend_from_slice(&chunk[..read]);
+            total += read;
+        }
+    }
+
+    /// Header of the first complete message in the buffer
+    pub fn peek_header(&self) -> io::Result<Option<WlHeader>> {
+        if self.in_data.len() < 8 {
+            return Ok(None);
+        }
+        let mut offset: usize = 0;
+        let header = WlHeader {
+            object: self.in_data.read_u32(&mut offset).unwrap(),
+            opcode: self.in_data.read_u16(&mut offset).unwrap(),
+            size:   self.in_data.read_u16(&mut offset).unwrap(),
+        };
+        if header.size < 8 || !header.size.is_multiple_of(4) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid message size {}", header.size)));
+        }
+        if self.in_data.len() < header.size as usize {
+            return Ok(None);
+        }
+        Ok(Some(header))
+    }
+
+    /// Remove the first message from the buffer together with the fds it carries
+    /// Returns None if the message or its fds didn't fully arrive yet
+    pub fn next_message(&mut self, fd_count: usize) -> io::Result<Option<RawEvent>> {
+        let Some(header) = self.peek_header()? else {
+            return Ok(None);
+        };
+        if self.in_fds.len() < fd_count {
+            return Ok(None);
+        }
+        let body = self.in_data[8..header.size as usize].to_vec();
+        self.in_data.drain(..header.size as usize);
+        let fds = self.in_fds.drain(..fd_count).collect();
+        Ok(Some(RawEvent { header, body, fds }))
+    }
+
+    /// Whether requests are waiting for the socket to become writable
+    pub fn has_queued(&self) -> bool {
+        !self.out_data.is_empty()
+    }
+
+    /// Queue request with its fds until the next flush
+    pub fn queue(&mut self, request: &[u8], fds: Vec<OwnedFd>) -> io::Result<()> {
+        if self.out_data.len() + request.len() > MAX_BUFFERED
+            || self.out_fds.len() + fds.len() > MAX_FDS_OUT
+        {
+            self.flush_blocking()?;
+        }
+        self.out_data.extend_from_slice(request);
+        self.out_fds.extend(fds);
+        Ok(())
+    }
+
+    /// Write as much of the queue as the socket takes
+    /// Returns an error of kind WouldBlock if the socket is full and requests are left
+    pub fn flush(&mut self) -> io::Result<()> {
+        while !self.out_data.is_empty() {
+            let written = if self.out_fds.is_empty() {
+                self.socket.write(&self.out_data)
+            } else {
+                let raw_fds: Vec<RawFd> = self.out_fds.iter().take(MAX_FDS_OUT).map(|fd| fd.as_raw_fd()).collect();
+                let mut ancillary_buf = [0u8; ANCILLARY_SIZE];
+                let mut ancillary = SocketAncillary::new(&mut ancillary_buf[..]);
+                if !ancillary.add_fds(&raw_fds) {
+                    return Err(io::Error::other("Failed to add FDs to ancillary data"));
+                }
+                let written = self.socket.send_vectored_with_ancillary(&[IoSlice::new(&self.out_data)], &mut ancillary);
+                // The fds went out as soon as any byte did, they can be closed on our side
+                if matches!(written, Ok(1..)) {
+                    self.out_fds.drain(..raw_fds.len());
+                }
+                written
+            };
+
+            match written {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "Compositor stopped accepting requests")),
+                Ok(written) => {
+                    self.out_data.drain(..written);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush the whole queue, waiting for the socket to drain if the compositor falls behind
+    pub fn flush_blocking(&mut self) -> io::Result<()> {
+        loop {
+            match self.flush() {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => self.wait_writable()?,
+                result => return result,
+            }
+        }
+    }
+
+    fn wait_writable(&self) -> io::Result<()> {
+        let mut fd = pollfd {
+            fd:         self.socket.as_raw_fd(),
+            events:     POLLOUT,
+            revents:    0,
+        };
+        if unsafe { poll(&mut fd, 1, -1) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for Connection {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
@@ -0,0 +1,188 @@
+use std::{collections::HashMap, error::Error, io, mem, os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd}, ptr, sync::Arc, time::Duration};
+
+use libc::{c_int, c_void, epoll_create1, epoll_ctl, epoll_event, epoll_wait, itimerspec, pthread_sigmask, read, sigaddset, sigemptyset, signalfd, signalfd_siginfo, sigset_t, timerfd_create, timerfd_settime, timespec, CLOCK_MONOTONIC, EPOLLIN, EPOLL_CLOEXEC, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD, SFD_CLOEXEC, SFD_NONBLOCK, SIG_BLOCK, TFD_CLOEXEC, TFD_NONBLOCK};
+
+use crate::wayland::wl_client::WlClient;
+
+const MAX_EVENTS: usize = 32;
+
+/// Called with its token and the epoll events when its fd is ready
+pub type Callback = Arc<
+    dyn Fn(&Arc<WlClient>, u64, u32) -> Result<(), Box<dyn Error>>
+    + Send + Sync
+>;
+
+struct Source {
+    fd:         RawFd,
+    // Timer and signal fds belong to the loop, other fds to whoever registered them
+    owned:      Option<OwnedFd>,
+    callback:   Callback,
+}
+
+/// epoll over the wayland socket, timers, signals, child pipes and the ipc socket
+pub struct EventLoop {
+    epoll:      OwnedFd,
+    sources:    HashMap<u64, Source>,
+    next_token: u64,
+}
+
+fn check(result: c_int) -> io::Result<c_int> {
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(result)
+}
+
+fn timespec(duration: Duration) -> timespec {
+    timespec {
+        tv_sec:     duration.as_secs() as _,
+        tv_nsec:    duration.subsec_nanos() as _,
+    }
+}
+
+/// Set of signals, blocked on the calling thread so they can be read from a signalfd instead
+pub fn block_signals(signals: &[c_int]) -> io::Result<sigset_t> {
+    unsafe {
+        let mut set: sigset_t = mem::zeroed();
+        sigemptyset(&mut set);
+        for &signal in signals {
+            sigaddset(&mut set, signal);
+        }
+        let result = pthread_sigmask(SIG_BLOCK, &set, ptr::null_mut());
+        if result != 0 {
+            return Err(io::Error::from_raw_os_error(result));
+        }
+        Ok(set)
+    }
+}
+
+impl EventLoop {
+    pub fn new() -> io::Result<Self> {
+        let epoll = check(unsafe { epoll_create1(EPOLL_CLOEXEC) })?;
+        Ok(EventLoop {
+            epoll:      unsafe { OwnedFd::from_raw_fd(epoll) },
+            sources:    HashMap::new(),
+            next_token: 0,
+        })
+    }
+
+    fn add_source(&mut self, fd: RawFd, owned: Option<OwnedFd>, events: u32, callback: Callback) -> io::Result<u64> {
+        let token = self.next_token;
+        let mut event = epoll_event { events, u64: token };
+        check(unsafe { epoll_ctl(self.epoll.as_raw_fd(), EPOLL_CTL_ADD, fd, &mut event) })?;
+        self.next_token += 1;
+        self.sources.insert(token, Source { fd, owned, callback });
+        Ok(token)
+    }
+
+    /// Watch fd for events, it has to stay open until the source is removed
+    pub fn add_fd<F>(&mut self, fd: RawFd, events: u32, callback: F) -> io::Result<u64>
+    where
+        F: Fn(&Arc<WlClient>, u64, u32) -> Result<(), Box<dyn Error>> + Send + Sync + 'static,
+    {
+        self.add_source(fd, None, events, Arc::new(callback))
+    }
+
+    /// Change the events watched for on a source
+    pub fn modify(&mut self, token: u64, events: u32) -> io::Result<()> {
+        let Some(source) = self.sources.get(&token) else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("No event source {}", token)));
+        };
+        let mut event = epoll_event { events, u64: token };
+        check(unsafe { epoll_ctl(self.epoll.as_raw_fd(), EPOLL_CTL_MOD, source.fd, &mut event) })?;
+        Ok(())
+    }
+
+    /// Stop watching a source, fds owned by the loop are closed
+    pub fn remove(&mut self, token: u64) -> io::Result<()> {
+        if let Some(source) = self.sources.remove(&token) {
+            check(unsafe { epoll_ctl(self.epoll.as_raw_fd(), EPOLL_CTL_DEL, source.fd, ptr::null_mut()) })?;
+            // Only close once it's out of the epoll set
+            drop(source.owned);
+        }
+        Ok(())
+    }
+
+    /// Create a disarmed timer, see set_timer
+    pub fn add_timer<F>(&mut self, callback: F) -> io::Result<u64>
+    where
+        F: Fn(&Arc<WlClient>) -> Result<(), Box<dyn Error>> + Send + Sync + 'static,
+    {
+        let fd = check(unsafe { timerfd_create(CLOCK_MONOTONIC, TFD_NONBLOCK | TFD_CLOEXEC) })?;
+        let timer = unsafe { OwnedFd::from_raw_fd(fd) };
+        self.add_source(fd, Some(timer), EPOLLIN as u32, Arc::new(move |client, _, _| {
+            let mut expirations = 0u64;
+            let size = mem::size_of::<u64>();
+            // Nothing to read means the timer was rearmed after it fired
+            if unsafe { read(fd, &mut expirations as *mut u64 as *mut c_void, size) } != size as isize {
+                return Ok(());
+            }
+            callback(client)
+        }))
+    }
+
+    /// Arm timer to fire after delay and then every interval, a delay of None disarms it
+    pub fn set_timer(&self, token: u64, delay: Option<Duration>, interval: Option<Duration>) -> io::Result<()> {
+        let Some(source) = self.sources.get(&token) else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("No timer {}", token)));
+        };
+        let spec = itimerspec {
+            // A zero delay would disarm the timer, fire as soon as possible instead
+            it_value:       timespec(delay.map_or(Duration::ZERO, |delay| delay.max(Duration::from_nanos(1)))),
+            it_interval:    timespec(interval.unwrap_or(Duration::ZERO)),
+        };
+        check(unsafe { timerfd_settime(source.fd, 0, &spec, ptr::null_mut()) })?;
+        Ok(())
+    }
+
+    /// Deliver signals, they have to be blocked with block_signals first
+    pub fn add_signals<F>(&mut self, signals: &sigset_t, callback: F) -> io::Result<u64>
+    where
+        F: Fn(&Arc<WlClient>, c_int) -> Result<(), Box<dyn Error>> + Send + Sync + 'static,
+    {
+        let fd = check(unsafe { signalfd(-1, signals, SFD_NONBLOCK | SFD_CLOEXEC) })?;
+        let owned = unsafe { OwnedFd::from_raw_fd(fd) };
+        self.add_source(fd, Some(owned), EPOLLIN as u32, Arc::new(move |client, _, _| {
+            let mut info: signalfd_siginfo = unsafe { mem::zeroed() };
+            let size = mem::size_of::<signalfd_siginfo>();
+            while unsafe { read(fd, &mut info as *mut signalfd_siginfo as *mut c_void, size) } == size as isize {
+                callback(client, info.ssi_signo as c_int)?;
+            }
+            Ok(())
+        }))
+    }
+
+    /// Block until a source is ready or timeout passes, returns the tokens and events of ready sources
+    pub fn wait(&self, timeout: Option<Duration>) -> io::Result<Vec<(u64, u32)>> {
+        let mut events = [epoll_event { events: 0, u64: 0 }; MAX_EVENTS];
+        let timeout = timeout.map_or(-1, |timeout| timeout.as_millis().min(c_int::MAX as u128) as c_int);
+        let count = match check(unsafe {
+            epoll_wait(self.epoll.as_raw_fd(), events.as_mut_ptr(), MAX_EVENTS as c_int, timeout)
+        }) {
+            Ok(count) => count as usize,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => 0,
+            Err(err) => return Err(err),
+        };
+        Ok(events[..count].iter().map(|event| (event.u64, event.events)).collect())
+    }
+
+    /// Callback of a source, None if it was removed in the meantime
+    pub fn callback(&self, token: u64) -> Option<Callback> {
+        self.sources.get(&token).map(|source| source.callback.clone())
+    }
+}
+
+impl WlClient {
+    /// Wait for event sources and run the callbacks of the ready ones
+    pub fn dispatch_sources(self: &Arc<Self>, timeout: Option<Duration>) -> Result<(), Box<dyn Error>> {
+        let ready = self.event_loop.lock().unwrap().wait(timeout)?;
+        for (token, events) in ready {
+            // An earlier callback may have removed the source
+            let Some(callback) = self.event_loop.lock().unwrap().callback(token) else {
+                continue;
+            };
+            callback(self, token, events)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use std::error::Error;
+
+use crate::wayland::{protocol::{wp_fractional_scale_manager_v1, wp_fractional_scale_v1, wp_viewport, wp_viewporter}, wl_client::WlClient};
+
+/// wp_fractional_scale_v1 scales are in 120ths
+const SCALE_DENOMINATOR: f64 = 120.0;
+
+impl WlClient {
+    /// Fractional scale and viewport objects for wl_surface, None unless both globals are there
+    pub fn init_fractional_scale(&self, wl_surface: u32) -> Result<Option<(u32, u32)>, Box<dyn Error>> {
+        let (Ok(manager), Ok(viewporter)) = (
+            self.global(&wp_fractional_scale_manager_v1::INTERFACE),
+            self.global(&wp_viewporter::INTERFACE),
+        ) else {
+            return Ok(None);
+        };
+
+        let fractional_scale = self.new_object(move |client, _, event| client.wp_fractional_scale_event(wl_surface, event));
+        self.send(manager, wp_fractional_scale_manager_v1::Request::GetFractionalScale {
+            id:         fractional_scale,
+            surface:    wl_surface,
+        })?;
+        let viewport = self.new_object_ignored(&wp_viewport::INTERFACE);
+        self.send(viewporter, wp_viewporter::Request::GetViewport { id: viewport, surface: wl_surface })?;
+
+        Ok(Some((fractional_scale, viewport)))
+    }
+
+    pub fn wp_fractional_scale_event(&self, wl_surface: u32, event: wp_fractional_scale_v1::Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            wp_fractional_scale_v1::Event::PreferredScale { scale } => {
+                let scale = scale as f64 / SCALE_DENOMINATOR;
+                println!("Preferred fractional scale: {}", scale);
+                self.surface_set_scale(wl_surface, scale)
+            }
+        }
+    }
+
+    /// Logical size the buffer is shown at, whatever its pixel size
+    pub fn wp_viewport_set_destination(&self, viewport: u32, width: usize, height: usize) -> Result<(), Box<dyn Error>> {
+        self.send(viewport, wp_viewport::Request::SetDestination { width: width as i32, height: height as i32 })
+    }
+}
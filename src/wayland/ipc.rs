@@ -0,0 +1,113 @@
+use std::{env::var, error::Error, fmt::Display, fs, io::{self, Read, Write}, os::{fd::AsRawFd, unix::net::{UnixListener, UnixStream}}, path::PathBuf, sync::Mutex};
+
+use libc::EPOLLIN;
+
+use crate::{config::parse_layer, wayland::wl_client::WlClient};
+
+#[derive(Debug)]
+pub struct IpcErr(pub String);
+
+impl Display for IpcErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "IPC error: {}", self.0)
+    }
+}
+
+impl Error for IpcErr {}
+
+/// Removes the socket file once the launcher is done
+pub struct IpcSocket {
+    path:   PathBuf,
+}
+
+impl Drop for IpcSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl WlClient {
+    /// Listen for newline separated commands on $XDG_RUNTIME_DIR/chlorostart-$WAYLAND_DISPLAY.sock
+    ///
+    /// `launch <command>` runs command, `layer <layer>` moves the launcher to another
+    /// layer, `quit` exits, every command is answered with `ok` or `error: <reason>`.
+    /// None if another instance is already listening there
+    pub fn ipc_listen(&self) -> Result<Option<IpcSocket>, Box<dyn Error>> {
+        let path = PathBuf::from(format!(
+            "{}/chlorostart-{}.sock",
+            var("XDG_RUNTIME_DIR")?,
+            var("WAYLAND_DISPLAY")?
+        ));
+        if UnixStream::connect(&path).is_ok() {
+            println!("Already running, {} is taken", path.display());
+            return Ok(None);
+        }
+        // Left behind by an instance that didn't exit cleanly
+        let _ = fs::remove_file(&path);
+
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+        let fd = listener.as_raw_fd();
+        self.event_loop.lock().unwrap().add_fd(fd, EPOLLIN as u32, move |client, _, _| {
+            loop {
+                match listener.accept() {
+                    Ok((stream, _)) => client.ipc_accept(stream)?,
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        })?;
+
+        Ok(Some(IpcSocket { path }))
+    }
+
+    fn ipc_accept(&self, stream: UnixStream) -> Result<(), Box<dyn Error>> {
+        stream.set_nonblocking(true)?;
+        let fd = stream.as_raw_fd();
+        let line = Mutex::new(Vec::new());
+
+        self.event_loop.lock().unwrap().add_fd(fd, EPOLLIN as u32, move |client, token, _| {
+            let mut line = line.lock().unwrap();
+            let mut chunk = [0u8; 256];
+            loop {
+                let read = match (&stream).read(&mut chunk) {
+                    Ok(read) => read,
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => 0,
+                };
+                if read == 0 {
+                    client.event_loop.lock().unwrap().remove(token)?;
+                    return Ok(());
+                }
+                line.extend_from_slice(&chunk[..read]);
+                while let Some(end) = line.iter().position(|&byte| byte == b'\n') {
+                    let command = String::from_utf8_lossy(&line[..end]).into_owned();
+                    line.drain(..=end);
+                    let reply = match client.ipc_command(command.trim()) {
+                        Ok(()) => "ok\n".to_string(),
+                        Err(err) => format!("error: {}\n", err),
+                    };
+                    // A client that doesn't read its replies doesn't get them
+                    let _ = (&stream).write_all(reply.as_bytes());
+                }
+            }
+        })?;
+
+        Ok(())
+    }
+
+    pub fn ipc_command(&self, command: &str) -> Result<(), Box<dyn Error>> {
+        let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+        match name {
+            "launch" if !args.trim().is_empty() => self.launch(args.trim()),
+            "quit" => self.exit(),
+            "layer" => {
+                let layer = parse_layer(args.trim()).ok_or(IpcErr(format!("Unknown layer \"{}\"", args.trim())))?;
+                self.set_layer(layer)
+            }
+            _ => Err(IpcErr(format!("Unknown command \"{}\"", command)).into()),
+        }
+    }
+}
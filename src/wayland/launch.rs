@@ -0,0 +1,114 @@
+use std::{error::Error, fs::File, io::{self, Read, Write}, mem, os::{fd::{AsRawFd, OwnedFd}, unix::process::CommandExt}, process::{Command, Stdio}, ptr, sync::{atomic::Ordering, Mutex}};
+
+use libc::{fcntl, pthread_sigmask, setsid, signal, sigset_t, waitpid, EPOLLIN, F_GETFL, F_SETFL, O_NONBLOCK, SIGPIPE, SIG_IGN, SIG_SETMASK, WEXITSTATUS, WIFEXITED, WNOHANG};
+
+use crate::wayland::wl_client::WlClient;
+
+impl WlClient {
+    /// Run command through the shell in a session of its own, so it outlives the launcher.
+    /// Its output is forwarded to ours line by line while we run
+    pub fn launch(&self, command: &str) -> Result<(), Box<dyn Error>> {
+        let mut process = Command::new("sh");
+        process.arg("-c").arg(command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        unsafe {
+            process.pre_exec(|| {
+                // The signals the event loop reads from its signalfd are blocked, don't pass that on
+                let empty: sigset_t = mem::zeroed();
+                match pthread_sigmask(SIG_SETMASK, &empty, ptr::null_mut()) {
+                    0 => {}
+                    err => return Err(io::Error::from_raw_os_error(err)),
+                }
+                // The pipes break once we exit, writing to them then fails with EPIPE
+                // instead of killing the app
+                signal(SIGPIPE, SIG_IGN);
+                // Leave our session, so hangups of our terminal don't reach the app
+                if setsid() < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let mut child = process.spawn()?;
+
+        let pid = child.id();
+        println!("Launched {}: {}", pid, command);
+        if let Some(stdout) = child.stdout.take() {
+            self.watch_pipe(pid, stdout.into(), false)?;
+        }
+        if let Some(stderr) = child.stderr.take() {
+            self.watch_pipe(pid, stderr.into(), true)?;
+        }
+
+        Ok(())
+    }
+
+    fn watch_pipe(&self, pid: u32, pipe: OwnedFd, stderr: bool) -> Result<(), Box<dyn Error>> {
+        unsafe {
+            let flags = fcntl(pipe.as_raw_fd(), F_GETFL);
+            fcntl(pipe.as_raw_fd(), F_SETFL, flags | O_NONBLOCK);
+        }
+        let pipe = File::from(pipe);
+        let fd = pipe.as_raw_fd();
+        let line = Mutex::new(Vec::new());
+
+        self.event_loop.lock().unwrap().add_fd(fd, EPOLLIN as u32, move |client, token, _| {
+            let mut line = line.lock().unwrap();
+            let mut chunk = [0u8; 1024];
+            // Once exit has run nothing is forwarded anymore, the pipe closes with us
+            let running = client.running.load(Ordering::Relaxed);
+            loop {
+                let read = match (&pipe).read(&mut chunk) {
+                    Ok(read) => read,
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(err.into()),
+                };
+                if read == 0 {
+                    if running && !line.is_empty() {
+                        forward_line(pid, &line, stderr);
+                    }
+                    client.event_loop.lock().unwrap().remove(token)?;
+                    return Ok(());
+                }
+                line.extend_from_slice(&chunk[..read]);
+                while let Some(end) = line.iter().position(|&byte| byte == b'\n') {
+                    if running {
+                        forward_line(pid, &line[..end], stderr);
+                    }
+                    line.drain(..=end);
+                }
+            }
+        })?;
+
+        Ok(())
+    }
+
+    /// Reap exited children so they don't linger as zombies
+    pub fn reap_children(&self) -> Result<(), Box<dyn Error>> {
+        loop {
+            let mut status = 0;
+            let pid = unsafe { waitpid(-1, &mut status, WNOHANG) };
+            if pid <= 0 {
+                return Ok(());
+            }
+            if WIFEXITED(status) {
+                println!("Process {} exited with status {}", pid, WEXITSTATUS(status));
+            } else {
+                println!("Process {} was killed", pid);
+            }
+        }
+    }
+}
+
+/// Write a line of a child's output to ours, a closed stdout of our own is no reason to fail
+fn forward_line(pid: u32, line: &[u8], stderr: bool) {
+    let line = format!("[{}] {}\n", pid, String::from_utf8_lossy(line));
+    let _ = if stderr {
+        io::stderr().write_all(line.as_bytes())
+    } else {
+        io::stdout().write_all(line.as_bytes())
+    };
+}
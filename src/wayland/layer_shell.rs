@@ -1,126 +1,165 @@
-use std::{error::Error, io::Write, sync::atomic::Ordering, thread, time::Duration};
-use crate::wayland::{surface::UnsetErr, vec_utils::WlMessage, wl_client::WlClient};
-
-const NAMESPACE: &str = "chlorostart";
-const OVERLAY: u32 = 3;
-const EXCLUSIVE: u32 = 1; // exclusize keyboard focus
+use std::{error::Error, fmt::Display};
+use crate::{config::OutputChoice, wayland::{protocol::{zwlr_layer_shell_v1, zwlr_layer_surface_v1}, surface::Role, wl_client::WlClient}};
+
+/// A request needs a newer version of a global than the compositor gave us
+#[derive(Debug)]
+pub struct UnsupportedErr {
+    pub interface:  &'static str,
+    pub required:   u32,
+    pub bound:      u32,
+}
 
-impl WlClient {
-    pub fn layer_shell_get_layer_surface(&self) -> Result<(), Box<dyn Error>> {
-        // TODO: Make sure layer_surface_id isn't already set
-        let object: u32 = self.layer_shell_id.load(Ordering::Relaxed);
-        if object == 0 {
-            return Err(UnsetErr("layer_shell_id".to_string()).into());
-        }
-        const OPCODE: u16 = 0;
-        let msg_size: u16 = 28 + (NAMESPACE.len()+1).next_multiple_of(4) as u16;
-        let output: u32 = 0;
+impl Display for UnsupportedErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} version {} is required, the compositor only offers version {}", self.interface, self.required, self.bound)
+    }
+}
 
-        let mut request = vec![0u8; msg_size as usize];
-        let mut offset: usize = 0;
+impl Error for UnsupportedErr {}
 
-        request.write_u32(&object,   &mut offset);
-        request.write_u16(&OPCODE,   &mut offset);
-        request.write_u16(&msg_size, &mut offset);
+impl WlClient {
+    /// Make wl_surface a layer surface placed as configured, committing is up to the caller
+    pub fn init_layer_surface(&self, wl_surface: u32, output: Option<u32>) -> Result<Role, Box<dyn Error>> {
+        let layer_surface = self.layer_shell_get_layer_surface(wl_surface, output)?;
+
+        let placement = &self.config.placement;
+        self.layer_surface_set_size(layer_surface, placement.width, placement.height)?;
+        self.layer_surface_set_anchor(layer_surface, placement.anchor)?;
+        self.layer_surface_set_margin(layer_surface, placement.margin)?;
+        self.layer_surface_set_exclusive_zone(layer_surface, placement.exclusive_zone)?;
+        self.layer_surface_set_keyboard_interactivity(layer_surface, placement.keyboard)?;
+
+        Ok(Role::Layer { layer_surface })
+    }
 
-        let current_id = self.current_id.fetch_add(1, Ordering::Relaxed) + 1;
-        request.write_u32(&current_id, &mut offset);
+    /// Output None lets the compositor pick one
+    pub fn layer_shell_get_layer_surface(&self, wl_surface: u32, output: Option<u32>) -> Result<u32, Box<dyn Error>> {
+        let object = self.global(&zwlr_layer_shell_v1::INTERFACE)?;
+
+        let id = self.new_object(move |client, id, event| {
+            client.layer_surface_event(wl_surface, id, event)
+        });
+        self.send(object, zwlr_layer_shell_v1::Request::GetLayerSurface {
+            id,
+            surface:    wl_surface,
+            output:     output.unwrap_or(0),
+            layer:      self.config.placement.layer as u32,
+            namespace:  self.config.placement.namespace.clone(),
+        })?;
+
+        Ok(id)
+    }
 
-        let surface_id = self.surface_id.load(Ordering::Relaxed);
-        if surface_id == 0 {
-            return Err(UnsetErr("surface_id".to_string()).into());
+    pub fn layer_surface_event(
+        &self,
+        wl_surface: u32,
+        layer_surface: u32,
+        event: zwlr_layer_surface_v1::Event
+    ) -> Result<(), Box<dyn Error>> {
+        match event {
+            zwlr_layer_surface_v1::Event::Configure { serial, width, height } => {
+                self.layer_surface_configure(wl_surface, layer_surface, serial, width, height)
+            }
+            zwlr_layer_surface_v1::Event::Closed => self.layer_surface_closed(wl_surface),
         }
-
-        request.write_u32(&surface_id, &mut offset);
-        request.write_u32(&output, &mut offset);
-        request.write_u32(&OVERLAY, &mut offset);
-        request.write_string(&NAMESPACE.to_string(), &mut offset);
-
-        self.socket.lock().unwrap().write(&request)?;
-        self.layer_surface_id.store(current_id, Ordering::Relaxed);
-
-        Ok(())
     }
 
-    pub fn layer_surface_configure(&self, event: &Vec<u8>) -> Result<(), Box<dyn Error + '_>> {
-        let mut offset: usize = 0;
-        let serial = event.read_u32(&mut offset);
-        let width  = event.read_u32(&mut offset);
-        let height = event.read_u32(&mut offset);
-
+    pub fn layer_surface_configure(
+        &self,
+        wl_surface: u32,
+        layer_surface: u32,
+        serial: u32,
+        width: u32,
+        height: u32
+    ) -> Result<(), Box<dyn Error>> {
         println!(
             "Configure layer surface:\n\twidth: {}\n\theight: {}",
             width,
             height
         );
 
-        // TODO: Resize based on configure
+        // Zero leaves the size up to us
+        let width = if width == 0 { self.config.placement.width } else { width };
+        let height = if height == 0 { self.config.placement.height } else { height };
+        self.surface_resize(wl_surface, width as usize, height as usize)?;
 
         // Ack configure
-        let object = self.layer_surface_id.load(Ordering::Relaxed);
-        if object == 0 {
-            return Err(UnsetErr("layer_surface_id".to_string()).into());
-        }
-        const OPCODE: u16 = 6;
-        const MSG_SIZE: u16 = 12;
-
-        let mut request = vec![0u8; MSG_SIZE as usize];
-        offset = 0;
-
-        request.write_u32(&object,   &mut offset);
-        request.write_u16(&OPCODE,   &mut offset);
-        request.write_u16(&MSG_SIZE, &mut offset);
-
-        request.write_u32(&serial, &mut offset);
-
-        self.socket.lock().unwrap().write(&request)?;
-        self.wl_surface_frame()?;
+        self.send(layer_surface, zwlr_layer_surface_v1::Request::AckConfigure { serial })?;
+        self.wl_surface_frame(wl_surface)?;
 
         Ok(())
     }
 
-    pub fn layer_surface_set_size(&self, width: u32, height: u32) -> Result<(), Box<dyn Error>> {
-        let object: u32 = self.layer_surface_id.load(Ordering::Relaxed);
-        if object == 0 {
-            return Err(UnsetErr("layer_surface_id".to_string()).into());
+    /// The compositor took the surface away, e.g. its output is gone
+    pub fn layer_surface_closed(&self, wl_surface: u32) -> Result<(), Box<dyn Error>> {
+        self.destroy_surface(wl_surface)?;
+        // With one launcher per output a new output brings it back
+        if self.surfaces.lock().unwrap().is_empty() && self.config.placement.output != OutputChoice::All {
+            return self.exit();
         }
-        const OPCODE: u16 = 0;
-        const MSG_SIZE: u16 = 20;
-
-        let mut request = vec![0u8; MSG_SIZE as usize];
-        let mut offset: usize = 0;
-
-        request.write_u32(&object,   &mut offset);
-        request.write_u16(&OPCODE,   &mut offset);
-        request.write_u16(&MSG_SIZE, &mut offset);
-
-        request.write_u32(&width,    &mut offset);
-        request.write_u32(&height,   &mut offset);
-
-        self.socket.lock().unwrap().write(&request)?;
+        self.flush()
+    }
 
-        Ok(())
+    pub fn layer_surface_set_size(&self, layer_surface: u32, width: u32, height: u32) -> Result<(), Box<dyn Error>> {
+        self.send(layer_surface, zwlr_layer_surface_v1::Request::SetSize { width, height })
     }
 
-    pub fn layer_surface_set_keyboard_interactivity(&self) -> Result<(), Box<dyn Error>> {
-        let object: u32 = self.layer_surface_id.load(Ordering::Relaxed);
-        if object == 0 {
-            return Err(UnsetErr("layer_surface_id".to_string()).into());
+    /// Move every launcher surface to layer
+    pub fn set_layer(&self, layer: zwlr_layer_shell_v1::Layer) -> Result<(), Box<dyn Error>> {
+        let surfaces: Vec<(u32, u32)> = self.surfaces.lock().unwrap().iter()
+            .filter_map(|surface| match surface.role {
+                Role::Layer { layer_surface } => Some((surface.wl_surface, layer_surface)),
+                Role::Toplevel { .. } => None,
+            })
+            .collect();
+        for (wl_surface, layer_surface) in surfaces {
+            self.layer_surface_set_layer(layer_surface, layer)?;
+            self.wl_surface_commit(wl_surface)?;
         }
-        const OPCODE: u16 = 4;
-        const MSG_SIZE: u16 = 12;
+        self.flush()
+    }
 
-        let mut request = vec![0u8; MSG_SIZE as usize];
-        let mut offset: usize = 0;
+    pub fn layer_surface_set_anchor(&self, layer_surface: u32, anchor: u32) -> Result<(), Box<dyn Error>> {
+        self.send(layer_surface, zwlr_layer_surface_v1::Request::SetAnchor { anchor })
+    }
 
-        request.write_u32(&object,   &mut offset);
-        request.write_u16(&OPCODE,   &mut offset);
-        request.write_u16(&MSG_SIZE, &mut offset);
+    /// Margins are top, right, bottom, left like in CSS
+    pub fn layer_surface_set_margin(&self, layer_surface: u32, margin: [i32; 4]) -> Result<(), Box<dyn Error>> {
+        let [top, right, bottom, left] = margin;
+        self.send(layer_surface, zwlr_layer_surface_v1::Request::SetMargin { top, right, bottom, left })
+    }
 
-        request.write_u32(&EXCLUSIVE, &mut offset);
+    pub fn layer_surface_set_exclusive_zone(&self, layer_surface: u32, zone: i32) -> Result<(), Box<dyn Error>> {
+        self.send(layer_surface, zwlr_layer_surface_v1::Request::SetExclusiveZone { zone })
+    }
 
-        self.socket.lock().unwrap().write(&request)?;
+    /// Move the surface to another layer, takes effect on the next commit
+    pub fn layer_surface_set_layer(&self, layer_surface: u32, layer: zwlr_layer_shell_v1::Layer) -> Result<(), Box<dyn Error>> {
+        // set_layer is new in version 2, the layer was fixed at creation before
+        let bound = self.global_version(&zwlr_layer_shell_v1::INTERFACE);
+        if bound < 2 {
+            return Err(UnsupportedErr { interface: zwlr_layer_shell_v1::INTERFACE.name, required: 2, bound }.into());
+        }
+        self.send(layer_surface, zwlr_layer_surface_v1::Request::SetLayer { layer: layer as u32 })
+    }
 
-        Ok(())
+    pub fn layer_surface_set_keyboard_interactivity(
+        &self,
+        layer_surface: u32,
+        mode: zwlr_layer_surface_v1::KeyboardInteractivity
+    ) -> Result<(), Box<dyn Error>> {
+        // On demand focus is new in version 4, older compositors only know none and exclusive.
+        // Exclusive would take the keyboard from every other app, so go without focus
+        let mode = if mode == zwlr_layer_surface_v1::KeyboardInteractivity::OnDemand
+            && self.global_version(&zwlr_layer_shell_v1::INTERFACE) < 4
+        {
+            println!("Layer shell is too old for on demand keyboard focus, the launcher won't get any");
+            zwlr_layer_surface_v1::KeyboardInteractivity::None
+        } else {
+            mode
+        };
+        self.send(layer_surface, zwlr_layer_surface_v1::Request::SetKeyboardInteractivity {
+            keyboard_interactivity: mode as u32,
+        })
     }
 }
@@ -0,0 +1,724 @@
+// Fake compositor for tests, speaks the wire protocol over one end of a socket pair
+// and records every request so tests can assert on what the client did
+
+#![allow(dead_code)]
+
+use std::{collections::{HashMap, VecDeque}, io, mem, net::Shutdown, os::{fd::{AsRawFd, FromRawFd, OwnedFd}, unix::net::UnixStream}, ptr, slice, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+
+use libc::{c_void, memfd_create, mmap, munmap, poll, pollfd, write, MAP_FAILED, MAP_SHARED, MFD_CLOEXEC, POLLIN, PROT_READ};
+
+use crate::wayland::{connection::Connection, protocol::{find_interface, wl_buffer, wl_callback, wl_compositor, wl_display, wl_keyboard, wl_output, wl_pointer, wl_touch, wl_registry, wl_seat, wl_shm, wl_shm_pool, wl_surface, xdg_surface, xdg_toplevel, xdg_wm_base, zwlr_layer_shell_v1, wp_fractional_scale_manager_v1, wp_viewport, wp_viewporter, zwlr_layer_surface_v1, zxdg_output_manager_v1, zxdg_output_v1, ArgKind, DecodeErr, Interface, Message, MessageDesc, WlMessage}};
+
+pub const OUTPUT_WIDTH: u32 = 1920;
+pub const OUTPUT_HEIGHT: u32 = 1080;
+
+/// Just enough of a keymap for xkb::gen_id_keysym_mapping
+pub const KEYMAP: &str = "\
+xkb_keymap {
+xkb_keycodes \"mock\" {
+    <ESC> = 9;
+    <AC01> = 38;
+};
+xkb_symbols \"mock\" {
+    key <ESC> { [ Escape ] };
+    key <AC01> { [ a, A ] };
+};
+};";
+
+/// Globals every test client needs
+pub fn default_globals() -> Vec<(&'static Interface, u32)> {
+    vec![
+        (&wl_compositor::INTERFACE, 6),
+        (&wl_shm::INTERFACE, 1),
+        (&xdg_wm_base::INTERFACE, 5),
+        (&zwlr_layer_shell_v1::INTERFACE, 4),
+        (&wl_seat::INTERFACE, 8),
+        (&wl_output::INTERFACE, 4),
+        (&zxdg_output_manager_v1::INTERFACE, 3),
+    ]
+}
+
+/// Decoded request of an interface the mock knows
+#[derive(Debug)]
+pub enum MockRequest {
+    Display(wl_display::Request),
+    Registry(wl_registry::Request),
+    Compositor(wl_compositor::Request),
+    Shm(wl_shm::Request),
+    ShmPool(wl_shm_pool::Request),
+    Buffer(wl_buffer::Request),
+    Surface(wl_surface::Request),
+    XdgWmBase(xdg_wm_base::Request),
+    XdgSurface(xdg_surface::Request),
+    XdgToplevel(xdg_toplevel::Request),
+    LayerShell(zwlr_layer_shell_v1::Request),
+    LayerSurface(zwlr_layer_surface_v1::Request),
+    Seat(wl_seat::Request),
+    Keyboard(wl_keyboard::Request),
+    Output(wl_output::Request),
+    XdgOutputManager(zxdg_output_manager_v1::Request),
+    XdgOutput(zxdg_output_v1::Request),
+    FractionalScaleManager(wp_fractional_scale_manager_v1::Request),
+    Viewporter(wp_viewporter::Request),
+    Viewport(wp_viewport::Request),
+    Other(&'static str, u16),
+}
+
+impl MockRequest {
+    fn decode(interface: &Interface, opcode: u16, body: &[u8], fds: &mut VecDeque<OwnedFd>) -> Result<Self, DecodeErr> {
+        Ok(match interface.name {
+            wl_display::NAME => MockRequest::Display(Message::decode(opcode, body, fds)?),
+            wl_registry::NAME => MockRequest::Registry(Message::decode(opcode, body, fds)?),
+            wl_compositor::NAME => MockRequest::Compositor(Message::decode(opcode, body, fds)?),
+            wl_shm::NAME => MockRequest::Shm(Message::decode(opcode, body, fds)?),
+            wl_shm_pool::NAME => MockRequest::ShmPool(Message::decode(opcode, body, fds)?),
+            wl_buffer::NAME => MockRequest::Buffer(Message::decode(opcode, body, fds)?),
+            wl_surface::NAME => MockRequest::Surface(Message::decode(opcode, body, fds)?),
+            xdg_wm_base::NAME => MockRequest::XdgWmBase(Message::decode(opcode, body, fds)?),
+            xdg_surface::NAME => MockRequest::XdgSurface(Message::decode(opcode, body, fds)?),
+            xdg_toplevel::NAME => MockRequest::XdgToplevel(Message::decode(opcode, body, fds)?),
+            zwlr_layer_shell_v1::NAME => MockRequest::LayerShell(Message::decode(opcode, body, fds)?),
+            zwlr_layer_surface_v1::NAME => MockRequest::LayerSurface(Message::decode(opcode, body, fds)?),
+            wl_seat::NAME => MockRequest::Seat(Message::decode(opcode, body, fds)?),
+            wl_keyboard::NAME => MockRequest::Keyboard(Message::decode(opcode, body, fds)?),
+            wl_output::NAME => MockRequest::Output(Message::decode(opcode, body, fds)?),
+            zxdg_output_manager_v1::NAME => MockRequest::XdgOutputManager(Message::decode(opcode, body, fds)?),
+            zxdg_output_v1::NAME => MockRequest::XdgOutput(Message::decode(opcode, body, fds)?),
+            wp_fractional_scale_manager_v1::NAME => MockRequest::FractionalScaleManager(Message::decode(opcode, body, fds)?),
+            wp_viewporter::NAME => MockRequest::Viewporter(Message::decode(opcode, body, fds)?),
+            wp_viewport::NAME => MockRequest::Viewport(Message::decode(opcode, body, fds)?),
+            _ => MockRequest::Other(interface.name, opcode),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Recorded {
+    pub object:     u32,
+    pub interface:  &'static str,
+    pub request:    MockRequest,
+}
+
+struct MockPool {
+    fd:     OwnedFd,
+    addr:   *mut c_void,
+    size:   usize,
+}
+
+impl MockPool {
+    fn map(fd: OwnedFd, size: usize) -> Self {
+        let addr = unsafe { mmap(ptr::null_mut(), size, PROT_READ, MAP_SHARED, fd.as_raw_fd(), 0) };
+        assert_ne!(addr, MAP_FAILED, "client sent a pool that can't be mapped");
+        MockPool { fd, addr, size }
+    }
+
+    fn pixels(&self, buffer: &MockBuffer) -> Vec<u32> {
+        let start = buffer.offset as usize;
+        let end = start + (buffer.stride * buffer.height) as usize;
+        assert!(end <= self.size, "buffer reaches past the end of its pool");
+        let bytes = unsafe { slice::from_raw_parts(self.addr as *const u8, self.size) };
+        let mut pixels = Vec::with_capacity((buffer.width * buffer.height) as usize);
+        for row in bytes[start..end].chunks(buffer.stride as usize) {
+            for pixel in row[..buffer.width as usize * 4].chunks(4) {
+                pixels.push(u32::from_ne_bytes(pixel.try_into().unwrap()));
+            }
+        }
+        pixels
+    }
+}
+
+impl Drop for MockPool {
+    fn drop(&mut self) {
+        unsafe { munmap(self.addr, self.size); }
+    }
+}
+
+unsafe impl Send for MockPool {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MockBuffer {
+    pub pool:   u32,
+    pub offset: i32,
+    pub width:  i32,
+    pub height: i32,
+    pub stride: i32,
+    pub format: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct MockSurface {
+    pub layer_surface:      Option<u32>,
+    pub layer_size:         (u32, u32),
+    pub interactivity:      u32,
+    pub buffer_scale:       i32,
+    pub fractional_scale:   Option<u32>,
+    pub viewport:           Option<u32>,
+    pub destination:        (i32, i32),
+    pub xdg_surface:        Option<u32>,
+    pub xdg_toplevel:       Option<u32>,
+    pub configured:         bool,
+    pub pending_buffer:     Option<u32>,
+    pub pending_frames:     Vec<u32>,
+    pub pending_damage:     Vec<(i32, i32, i32, i32)>,
+    /// Buffer, size and pixels of the last commit that had a buffer attached
+    pub buffer:             Option<u32>,
+    pub size:               (i32, i32),
+    pub pixels:             Vec<u32>,
+    /// Buffer damage of the last commit
+    pub damage:             Vec<(i32, i32, i32, i32)>,
+    pub frame_callbacks:    Vec<u32>,
+    pub commits:            usize,
+}
+
+impl MockSurface {
+    pub fn pixel(&self, x: i32, y: i32) -> u32 {
+        self.pixels[(y * self.size.0 + x) as usize]
+    }
+}
+
+pub struct MockState {
+    connection:         Connection,
+    /// Registry names are indices plus one, removed ones stay as None
+    globals:            Vec<Option<(&'static Interface, u32)>>,
+    registries:         Vec<u32>,
+    /// Registry name of every bound wl_output, by object id
+    pub outputs:        HashMap<u32, u32>,
+    objects:            HashMap<u32, &'static Interface>,
+    pools:              HashMap<u32, MockPool>,
+    pub buffers:        HashMap<u32, MockBuffer>,
+    /// Keep buffers instead of releasing them, like a compositor that is still reading them
+    pub hold_buffers:   bool,
+    pub held:           Vec<u32>,
+    pub surfaces:       HashMap<u32, MockSurface>,
+    pub keyboards:      Vec<u32>,
+    pub pointers:       Vec<u32>,
+    pub touches:        Vec<u32>,
+    pub focused:        Option<u32>,
+    pub requests:       Vec<Recorded>,
+    serial:             u32,
+}
+
+impl MockState {
+    pub fn send<E: Message>(&mut self, object: u32, event: E) {
+        let mut fds = Vec::new();
+        let event = event.encode(object, &mut fds);
+        self.connection.queue(&event, fds).unwrap();
+        // The client may be gone already, the test notices that on its own
+        let _ = self.connection.flush_blocking();
+    }
+
+    pub fn next_serial(&mut self) -> u32 {
+        self.serial += 1;
+        self.serial
+    }
+
+    /// How many wl_output globals were announced before name, outputs sit side by side in that order
+    fn output_index(&self, name: u32) -> i32 {
+        self.globals[..name as usize - 1].iter()
+            .filter(|global| global.is_some_and(|(interface, _)| interface.name == wl_output::NAME))
+            .count() as i32
+    }
+
+    /// Announce a new global to every registry, returns its name
+    pub fn add_global(&mut self, interface: &'static Interface, version: u32) -> u32 {
+        self.globals.push(Some((interface, version)));
+        let name = self.globals.len() as u32;
+        for registry in self.registries.clone() {
+            self.send(registry, wl_registry::Event::Global { name, interface: interface.name.to_string(), version });
+        }
+        name
+    }
+
+    pub fn remove_global(&mut self, name: u32) {
+        self.globals[name as usize - 1] = None;
+        for registry in self.registries.clone() {
+            self.send(registry, wl_registry::Event::GlobalRemove { name });
+        }
+    }
+
+    /// Move keyboard focus to surface, or away from any when None
+    pub fn focus(&mut self, surface: Option<u32>) {
+        let serial = self.next_serial();
+        for keyboard in self.keyboards.clone() {
+            match (self.focused, surface) {
+                (Some(old), _) => self.send(keyboard, wl_keyboard::Event::Leave { serial, surface: old }),
+                (None, None) => continue,
+                _ => {}
+            }
+            if let Some(surface) = surface {
+                self.send(keyboard, wl_keyboard::Event::Enter { serial, surface, keys: Vec::new() });
+            }
+        }
+        self.focused = surface;
+    }
+
+    /// The surface a layer surface was created for
+    pub fn layer_surface_parent(&self, layer_surface: u32) -> Option<u32> {
+        self.surfaces.iter()
+            .find(|(_, surface)| surface.layer_surface == Some(layer_surface))
+            .map(|(id, _)| *id)
+    }
+
+    pub fn requests_to(&self, interface: &Interface) -> impl Iterator<Item = &Recorded> {
+        self.requests.iter().filter(move |recorded| recorded.interface == interface.name)
+    }
+
+    /// Read everything the client sent, false once it hung up
+    fn receive(&mut self) -> bool {
+        match self.connection.read() {
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return true,
+            Err(_) => return false,
+        }
+        loop {
+            let header = match self.connection.peek_header() {
+                Ok(Some(header)) => header,
+                Ok(None) => return true,
+                Err(err) => panic!("client sent garbage: {}", err),
+            };
+            let interface = *self.objects.get(&header.object)
+                .unwrap_or_else(|| panic!("request for unknown object {}", header.object));
+            let message = interface.requests.get(header.opcode as usize)
+                .unwrap_or_else(|| panic!("unknown opcode {} for {}", header.opcode, interface.name));
+            let Some(mut raw) = self.connection.next_message(message.fd_count()).unwrap() else {
+                return true;
+            };
+
+            for (id, new_interface) in new_ids(message, &raw.body) {
+                let new_interface = find_interface(&new_interface)
+                    .unwrap_or_else(|| panic!("client created unknown interface {}", new_interface));
+                self.objects.insert(id, new_interface);
+            }
+            let request = MockRequest::decode(interface, raw.header.opcode, &raw.body, &mut raw.fds)
+                .unwrap_or_else(|err| panic!("{}", err));
+            self.handle(raw.header.object, &request);
+            if message.destructor {
+                self.objects.remove(&raw.header.object);
+                self.send(1, wl_display::Event::DeleteId { id: raw.header.object });
+            }
+            self.requests.push(Recorded { object: raw.header.object, interface: interface.name, request });
+        }
+    }
+
+    fn handle(&mut self, object: u32, request: &MockRequest) {
+        match request {
+            MockRequest::Display(wl_display::Request::Sync { callback }) => {
+                self.send(*callback, wl_callback::Event::Done { callback_data: 0 });
+                self.objects.remove(callback);
+                self.send(1, wl_display::Event::DeleteId { id: *callback });
+            }
+            MockRequest::Display(wl_display::Request::GetRegistry { registry }) => {
+                self.registries.push(*registry);
+                for (name, global) in self.globals.clone().into_iter().enumerate() {
+                    if let Some((interface, version)) = global {
+                        self.send(*registry, wl_registry::Event::Global {
+                            name:       name as u32 + 1,
+                            interface:  interface.name.to_string(),
+                            version,
+                        });
+                    }
+                }
+            }
+            MockRequest::Registry(wl_registry::Request::Bind { name, interface, version, id }) if interface == wl_output::NAME => {
+                self.outputs.insert(*id, *name);
+                let index = self.output_index(*name);
+                self.send(*id, wl_output::Event::Geometry {
+                    x:                  index * OUTPUT_WIDTH as i32,
+                    y:                  0,
+                    physical_width:     530,
+                    physical_height:    300,
+                    subpixel:           wl_output::Subpixel::Unknown as i32,
+                    make:               "Mock".to_string(),
+                    model:              "Monitor".to_string(),
+                    transform:          wl_output::Transform::Normal as i32,
+                });
+                self.send(*id, wl_output::Event::Mode {
+                    flags:      wl_output::Mode::Current as u32 | wl_output::Mode::Preferred as u32,
+                    width:      OUTPUT_WIDTH as i32,
+                    height:     OUTPUT_HEIGHT as i32,
+                    refresh:    60000,
+                });
+                if *version >= 2 {
+                    self.send(*id, wl_output::Event::Scale { factor: 1 });
+                }
+                if *version >= 4 {
+                    self.send(*id, wl_output::Event::Name { name: output_name(index) });
+                    self.send(*id, wl_output::Event::Description { description: format!("Mock monitor {}", index) });
+                }
+                if *version >= 2 {
+                    self.send(*id, wl_output::Event::Done);
+                }
+            }
+            MockRequest::XdgOutputManager(zxdg_output_manager_v1::Request::GetXdgOutput { id, output }) => {
+                let index = self.output_index(self.outputs[output]);
+                self.send(*id, zxdg_output_v1::Event::LogicalPosition { x: index * OUTPUT_WIDTH as i32, y: 0 });
+                self.send(*id, zxdg_output_v1::Event::LogicalSize { width: OUTPUT_WIDTH as i32, height: OUTPUT_HEIGHT as i32 });
+                self.send(*id, zxdg_output_v1::Event::Name { name: output_name(index) });
+                self.send(*id, zxdg_output_v1::Event::Description { description: format!("Mock monitor {}", index) });
+                // Only up to version 2, wl_output.done covers it after
+                self.send(*id, zxdg_output_v1::Event::Done);
+            }
+            MockRequest::Registry(wl_registry::Request::Bind { interface, id, .. }) => {
+                if interface == wl_shm::NAME {
+                    self.send(*id, wl_shm::Event::Format { format: wl_shm::Format::Argb8888 as u32 });
+                    self.send(*id, wl_shm::Event::Format { format: wl_shm::Format::Xrgb8888 as u32 });
+                    self.send(*id, wl_shm::Event::Format { format: wl_shm::Format::Xrgb2101010 as u32 });
+                } else if interface == wl_seat::NAME {
+                    let capabilities = wl_seat::Capability::Keyboard as u32 | wl_seat::Capability::Pointer as u32 | wl_seat::Capability::Touch as u32;
+                    self.send(*id, wl_seat::Event::Capabilities { capabilities });
+                    self.send(*id, wl_seat::Event::Name { name: "seat0".to_string() });
+                }
+            }
+            MockRequest::Compositor(wl_compositor::Request::CreateSurface { id }) => {
+                self.surfaces.insert(*id, MockSurface::default());
+            }
+            MockRequest::Shm(wl_shm::Request::CreatePool { id, fd, size }) => {
+                self.pools.insert(*id, MockPool::map(fd.try_clone().unwrap(), *size as usize));
+            }
+            MockRequest::ShmPool(wl_shm_pool::Request::CreateBuffer { id, offset, width, height, stride, format }) => {
+                self.buffers.insert(*id, MockBuffer {
+                    pool:   object,
+                    offset: *offset,
+                    width:  *width,
+                    height: *height,
+                    stride: *stride,
+                    format: *format,
+                });
+            }
+            MockRequest::ShmPool(wl_shm_pool::Request::Resize { size }) => {
+                let pool = self.pools.remove(&object).unwrap();
+                let fd = pool.fd.try_clone().unwrap();
+                drop(pool);
+                self.pools.insert(object, MockPool::map(fd, *size as usize));
+            }
+            MockRequest::Buffer(wl_buffer::Request::Destroy) => {
+                self.buffers.remove(&object);
+            }
+            MockRequest::Surface(wl_surface::Request::Attach { buffer, .. }) => {
+                assert!(
+                    !self.held.contains(buffer) && self.surfaces.values().all(|surface| surface.buffer != Some(*buffer)),
+                    "client attached buffer {} while the compositor holds it", buffer
+                );
+                self.surfaces.get_mut(&object).unwrap().pending_buffer = Some(*buffer);
+            }
+            MockRequest::Surface(wl_surface::Request::Frame { callback }) => {
+                self.surfaces.get_mut(&object).unwrap().pending_frames.push(*callback);
+            }
+            MockRequest::Surface(wl_surface::Request::DamageBuffer { x, y, width, height }) => {
+                self.surfaces.get_mut(&object).unwrap().pending_damage.push((*x, *y, *width, *height));
+            }
+            MockRequest::Surface(wl_surface::Request::SetBufferScale { scale }) => {
+                self.surfaces.get_mut(&object).unwrap().buffer_scale = *scale;
+            }
+            MockRequest::Surface(wl_surface::Request::Commit) => self.commit(object),
+            MockRequest::FractionalScaleManager(wp_fractional_scale_manager_v1::Request::GetFractionalScale { id, surface }) => {
+                self.surfaces.get_mut(surface).unwrap().fractional_scale = Some(*id);
+            }
+            MockRequest::Viewporter(wp_viewporter::Request::GetViewport { id, surface }) => {
+                self.surfaces.get_mut(surface).unwrap().viewport = Some(*id);
+            }
+            MockRequest::Viewport(wp_viewport::Request::SetDestination { width, height }) => {
+                let surface = self.surfaces.values_mut().find(|surface| surface.viewport == Some(object)).unwrap();
+                surface.destination = (*width, *height);
+            }
+            MockRequest::LayerShell(zwlr_layer_shell_v1::Request::GetLayerSurface { id, surface, .. }) => {
+                self.surfaces.get_mut(surface).unwrap().layer_surface = Some(*id);
+            }
+            MockRequest::XdgWmBase(xdg_wm_base::Request::GetXdgSurface { id, surface }) => {
+                self.surfaces.get_mut(surface).unwrap().xdg_surface = Some(*id);
+            }
+            MockRequest::XdgSurface(xdg_surface::Request::GetToplevel { id }) => {
+                let surface = self.surfaces.values_mut().find(|surface| surface.xdg_surface == Some(object)).unwrap();
+                surface.xdg_toplevel = Some(*id);
+            }
+            MockRequest::LayerSurface(zwlr_layer_surface_v1::Request::SetSize { width, height }) => {
+                let surface = self.layer_surface_parent(object).unwrap();
+                self.surfaces.get_mut(&surface).unwrap().layer_size = (*width, *height);
+            }
+            MockRequest::LayerSurface(zwlr_layer_surface_v1::Request::SetKeyboardInteractivity { keyboard_interactivity }) => {
+                let surface = self.layer_surface_parent(object).unwrap();
+                self.surfaces.get_mut(&surface).unwrap().interactivity = *keyboard_interactivity;
+            }
+            MockRequest::Seat(wl_seat::Request::GetKeyboard { id }) => {
+                self.keyboards.push(*id);
+                let (fd, size) = keymap_fd();
+                self.send(*id, wl_keyboard::Event::Keymap {
+                    format: wl_keyboard::KeymapFormat::XkbV1 as u32,
+                    fd,
+                    size,
+                });
+                // Repeating is left to the compositor, like with wl_keyboard v10
+                self.send(*id, wl_keyboard::Event::RepeatInfo { rate: 0, delay: 0 });
+            }
+            MockRequest::Seat(wl_seat::Request::GetPointer { id }) => self.pointers.push(*id),
+            MockRequest::Seat(wl_seat::Request::GetTouch { id }) => self.touches.push(*id),
+            _ => {}
+        }
+    }
+
+    fn commit(&mut self, id: u32) {
+        let mut configure = None;
+        let mut toplevel_configure = None;
+        let mut release = None;
+        let surface = self.surfaces.get_mut(&id).unwrap();
+        surface.commits += 1;
+        surface.frame_callbacks.append(&mut surface.pending_frames);
+        surface.damage = mem::take(&mut surface.pending_damage);
+        if let Some(layer_surface) = surface.layer_surface
+            && !surface.configured
+        {
+            surface.configured = true;
+            let (width, height) = surface.layer_size;
+            configure = Some((
+                layer_surface,
+                if width == 0 { OUTPUT_WIDTH } else { width },
+                if height == 0 { OUTPUT_HEIGHT } else { height },
+            ));
+        }
+        if let (Some(xdg_surface), Some(xdg_toplevel)) = (surface.xdg_surface, surface.xdg_toplevel)
+            && !surface.configured
+        {
+            surface.configured = true;
+            toplevel_configure = Some((xdg_surface, xdg_toplevel));
+        }
+        // New windows and exclusive layer surfaces get the keyboard as soon as they are mapped
+        let focus = surface.buffer.is_none() && surface.pending_buffer.is_some()
+            && (surface.xdg_toplevel.is_some()
+                || surface.interactivity == zwlr_layer_surface_v1::KeyboardInteractivity::Exclusive as u32);
+        if let Some(buffer_id) = surface.pending_buffer.take() {
+            let buffer = self.buffers[&buffer_id];
+            surface.pixels = self.pools[&buffer.pool].pixels(&buffer);
+            surface.size = (buffer.width, buffer.height);
+            release = surface.buffer.replace(buffer_id).filter(|previous| *previous != buffer_id);
+        }
+
+        if let Some(previous) = release
+            && self.buffers.contains_key(&previous)
+        {
+            if self.hold_buffers {
+                self.held.push(previous);
+            } else {
+                self.send(previous, wl_buffer::Event::Release);
+            }
+        }
+        if focus {
+            self.focus(Some(id));
+        }
+        if let Some((layer_surface, width, height)) = configure {
+            let serial = self.next_serial();
+            self.send(layer_surface, zwlr_layer_surface_v1::Event::Configure { serial, width, height });
+        }
+        if let Some((xdg_surface, xdg_toplevel)) = toplevel_configure {
+            // Leaving the size to the client like a floating window
+            self.send(xdg_toplevel, xdg_toplevel::Event::Configure { width: 0, height: 0, states: Vec::new() });
+            let serial = self.next_serial();
+            self.send(xdg_surface, xdg_surface::Event::Configure { serial });
+        }
+    }
+}
+
+fn output_name(index: i32) -> String {
+    format!("MOCK-{}", index + 1)
+}
+
+/// Ids and interfaces of the objects a request creates
+fn new_ids(message: &MessageDesc, body: &[u8]) -> Vec<(u32, String)> {
+    let mut offset = 0;
+    let mut ids = Vec::new();
+    for arg in message.args {
+        match arg.kind {
+            ArgKind::String => { body.read_opt_string(&mut offset).unwrap(); }
+            ArgKind::Array => { body.read_array(&mut offset).unwrap(); }
+            ArgKind::Fd => {}
+            ArgKind::NewId => {
+                let interface = match arg.interface {
+                    Some(interface) => interface.to_string(),
+                    None => {
+                        let interface = body.read_string(&mut offset).unwrap();
+                        body.read_u32(&mut offset).unwrap();
+                        interface
+                    }
+                };
+                ids.push((body.read_u32(&mut offset).unwrap(), interface));
+            }
+            _ => { body.read_u32(&mut offset).unwrap(); }
+        }
+    }
+    ids
+}
+
+fn keymap_fd() -> (OwnedFd, u32) {
+    let fd = unsafe { OwnedFd::from_raw_fd(memfd_create(c"mock-keymap".as_ptr(), MFD_CLOEXEC)) };
+    let keymap = format!("{}\0", KEYMAP);
+    let written = unsafe { write(fd.as_raw_fd(), keymap.as_ptr() as *const c_void, keymap.len()) };
+    assert_eq!(written, keymap.len() as isize);
+    (fd, keymap.len() as u32)
+}
+
+/// Hangs up on the client when the mock thread ends, also when it panics,
+/// so a test blocked on the client errors out instead of hanging
+struct HangUp(UnixStream);
+
+impl Drop for HangUp {
+    fn drop(&mut self) {
+        let _ = self.0.shutdown(Shutdown::Both);
+    }
+}
+
+pub struct MockCompositor {
+    state:  Arc<Mutex<MockState>>,
+    stop:   Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockCompositor {
+    /// Start serving globals, returns the socket end the client connects with
+    pub fn new(globals: Vec<(&'static Interface, u32)>) -> (Self, UnixStream) {
+        let (server, client) = UnixStream::pair().unwrap();
+        server.set_nonblocking(true).unwrap();
+        let hang_up = HangUp(server.try_clone().unwrap());
+        let fd = server.as_raw_fd();
+
+        let mut objects = HashMap::new();
+        objects.insert(1, &wl_display::INTERFACE);
+        let state = Arc::new(Mutex::new(MockState {
+            connection:   Connection::new(server),
+            globals:      globals.into_iter().map(Some).collect(),
+            registries:   Vec::new(),
+            outputs:      HashMap::new(),
+            objects,
+            pools:        HashMap::new(),
+            buffers:      HashMap::new(),
+            hold_buffers: false,
+            held:         Vec::new(),
+            surfaces:     HashMap::new(),
+            keyboards:    Vec::new(),
+            pointers:     Vec::new(),
+            touches:      Vec::new(),
+            focused:      None,
+            requests:     Vec::new(),
+            serial:       0,
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let state = state.clone();
+            let stop = stop.clone();
+            thread::Builder::new().name("mock-compositor".to_string()).spawn(move || {
+                let _hang_up = hang_up;
+                while !stop.load(Ordering::Relaxed) {
+                    let mut readable = pollfd { fd, events: POLLIN, revents: 0 };
+                    if unsafe { poll(&mut readable, 1, 10) } <= 0 {
+                        continue;
+                    }
+                    if !state.lock().unwrap().receive() {
+                        return;
+                    }
+                }
+            }).unwrap()
+        };
+
+        (MockCompositor { state, stop, thread: Some(thread) }, client)
+    }
+
+    pub fn with_state<R>(&self, f: impl FnOnce(&mut MockState) -> R) -> R {
+        f(&mut self.state.lock().unwrap())
+    }
+
+    /// Wait until the client's requests make condition true, false after a timeout
+    pub fn wait_for(&self, condition: impl Fn(&MockState) -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if condition(&self.state.lock().unwrap()) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        false
+    }
+
+    /// Send a key event to every keyboard, key is an evdev code
+    pub fn key(&self, key: u32, state: wl_keyboard::KeyState) {
+        self.with_state(|mock| {
+            let serial = mock.next_serial();
+            for keyboard in mock.keyboards.clone() {
+                mock.send(keyboard, wl_keyboard::Event::Key { serial, time: 0, key, state: state as u32 });
+            }
+        });
+    }
+
+    /// Send the events events makes from a serial to every pointer, as one frame
+    pub fn pointer(&self, events: impl Fn(u32) -> Vec<wl_pointer::Event>) {
+        self.with_state(|mock| {
+            let serial = mock.next_serial();
+            for pointer in mock.pointers.clone() {
+                for event in events(serial) {
+                    mock.send(pointer, event);
+                }
+                mock.send(pointer, wl_pointer::Event::Frame);
+            }
+        });
+    }
+
+    /// Send the events events makes from a serial to every touch device, as one frame
+    pub fn touch(&self, events: impl Fn(u32) -> Vec<wl_touch::Event>) {
+        self.with_state(|mock| {
+            let serial = mock.next_serial();
+            for touch in mock.touches.clone() {
+                for event in events(serial) {
+                    mock.send(touch, event);
+                }
+                mock.send(touch, wl_touch::Event::Frame);
+            }
+        });
+    }
+
+    /// Configure every layer surface to a new size, like after an output change
+    pub fn configure(&self, width: u32, height: u32) {
+        self.with_state(|mock| {
+            let layer_surfaces: Vec<u32> = mock.surfaces.values().filter_map(|surface| surface.layer_surface).collect();
+            for layer_surface in layer_surfaces {
+                let serial = mock.next_serial();
+                mock.send(layer_surface, zwlr_layer_surface_v1::Event::Configure { serial, width, height });
+            }
+        });
+    }
+
+    /// Release the held buffers and stop holding on to new ones
+    pub fn release_held(&self) {
+        self.with_state(|mock| {
+            mock.hold_buffers = false;
+            for buffer in mock.held.drain(..).collect::<Vec<u32>>() {
+                if mock.buffers.contains_key(&buffer) {
+                    mock.send(buffer, wl_buffer::Event::Release);
+                }
+            }
+        });
+    }
+
+    /// Fire the frame callbacks of every surface as if their content was presented
+    pub fn frame_done(&self) {
+        self.with_state(|mock| {
+            let callbacks: Vec<u32> = mock.surfaces.values_mut()
+                .flat_map(|surface| surface.frame_callbacks.drain(..))
+                .collect();
+            for callback in callbacks {
+                mock.send(callback, wl_callback::Event::Done { callback_data: 0 });
+                mock.objects.remove(&callback);
+                mock.send(1, wl_display::Event::DeleteId { id: callback });
+            }
+        });
+    }
+}
+
+impl Drop for MockCompositor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take()
+            && let Err(panic) = thread.join()
+            && !thread::panicking()
+        {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
@@ -1,10 +1,29 @@
 
 pub mod layer_shell;
+pub mod xdg_shell;
+pub mod output;
+pub mod fractional_scale;
 pub mod surface;
+pub mod swapchain;
 pub mod wl_client;
 pub mod wl_shm;
 pub mod vec_utils;
+pub mod protocol;
+pub mod object_map;
+pub mod connection;
+pub mod trace;
+pub mod event_loop;
+pub mod launch;
+pub mod ipc;
 pub mod shm;
 pub mod wl_registry;
+pub mod wl_display;
 pub mod seat;
+pub mod pointer;
+pub mod touch;
 pub mod xkb;
+
+#[cfg(test)]
+mod mock_compositor;
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,98 @@
+use std::{collections::{HashMap, VecDeque}, error::Error, fmt::Debug, os::fd::OwnedFd, sync::Arc};
+
+use crate::wayland::{protocol::{wl_display, Interface}, wl_client::WlClient};
+
+/// Decodes and handles one event for the object it is registered with
+pub type Handler = Arc<
+    dyn Fn(&Arc<WlClient>, u32, u16, &[u8], &mut VecDeque<OwnedFd>) -> Result<(), Box<dyn Error>>
+    + Send + Sync
+>;
+
+pub struct Object {
+    pub interface:  &'static Interface,
+    pub handler:    Option<Handler>,
+    /// Version a global was bound with, 1 for everything else
+    pub version:    u32,
+    /// Destroyed by us but not yet confirmed with delete_id, events still in flight are
+    /// decoded with its interface and dropped
+    pub zombie:     bool,
+}
+
+/// Client side object ids, the interface behind each id and its event handler
+pub struct ObjectMap {
+    objects:    HashMap<u32, Object>,
+    free_ids:   Vec<u32>,
+    next_id:    u32,
+}
+
+impl ObjectMap {
+    pub fn new() -> Self {
+        let mut objects = HashMap::new();
+        objects.insert(1, Object { interface: &wl_display::INTERFACE, handler: None, version: 1, zombie: false });
+        ObjectMap {
+            objects,
+            free_ids:   Vec::new(),
+            next_id:    2,
+        }
+    }
+
+    /// Insert object under a free id, ids released by the server are reused first
+    pub fn allocate(&mut self, object: Object) -> u32 {
+        let id = self.free_ids.pop().unwrap_or_else(|| {
+            self.next_id += 1;
+            self.next_id - 1
+        });
+        self.objects.insert(id, object);
+        id
+    }
+
+    pub fn set_handler(&mut self, id: u32, handler: Handler) {
+        if let Some(object) = self.objects.get_mut(&id) {
+            object.handler = Some(handler);
+        }
+    }
+
+    pub fn set_version(&mut self, id: u32, version: u32) {
+        if let Some(object) = self.objects.get_mut(&id) {
+            object.version = version;
+        }
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Object> {
+        self.objects.get(&id)
+    }
+
+    /// Id of the first object implementing interface, used for singleton globals
+    pub fn find(&self, interface: &Interface) -> Option<u32> {
+        self.objects.iter()
+            .filter(|(_, object)| object.interface.name == interface.name && !object.zombie)
+            .map(|(id, _)| *id)
+            .min()
+    }
+
+    /// Stop handling events for object after a destructor request, its id stays taken
+    /// until the server releases it
+    pub fn destroy(&mut self, id: u32) {
+        if let Some(object) = self.objects.get_mut(&id) {
+            object.handler = None;
+            object.zombie = true;
+        }
+    }
+
+    /// Forget object and make its id available again, called for wl_display.delete_id
+    pub fn release(&mut self, id: u32) {
+        if self.objects.remove(&id).is_some() {
+            self.free_ids.push(id);
+        }
+    }
+}
+
+impl Debug for ObjectMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut ids: Vec<&u32> = self.objects.keys().collect();
+        ids.sort();
+        f.debug_map()
+            .entries(ids.into_iter().map(|id| (id, self.objects[id].interface.name)))
+            .finish()
+    }
+}
@@ -0,0 +1,187 @@
+use std::{error::Error, sync::atomic::Ordering};
+
+use crate::{config::OutputChoice, wayland::{protocol::{wl_output, zwlr_layer_shell_v1, zxdg_output_manager_v1, zxdg_output_v1}, wl_client::WlClient}};
+
+/// A monitor as wl_output and xdg_output describe it
+#[derive(Debug, Clone, Default)]
+pub struct Output {
+    pub wl_output:      u32,
+    pub xdg_output:     Option<u32>,
+    /// Registry name, global_remove refers to the output by it
+    pub global:         u32,
+    pub name:           Option<String>,
+    pub description:    Option<String>,
+    /// Logical position and size in the compositor's space
+    pub x:              i32,
+    pub y:              i32,
+    pub width:          i32,
+    pub height:         i32,
+    pub scale:          i32,
+    /// Set by the first done, every property has been sent by then
+    pub ready:          bool,
+}
+
+impl WlClient {
+    /// Bind a wl_output global and start tracking it
+    pub fn wl_output_new(&self, global: u32) -> u32 {
+        let wl_output = self.new_object(|client, id, event| client.wl_output_event(id, event));
+        self.outputs.lock().unwrap().push(Output { wl_output, global, scale: 1, ..Default::default() });
+        wl_output
+    }
+
+    /// Ask for the xdg_output of every output that doesn't have one yet, once both globals are bound
+    pub fn xdg_output_manager_get_outputs(&self) -> Result<(), Box<dyn Error>> {
+        let Ok(manager) = self.global(&zxdg_output_manager_v1::INTERFACE) else {
+            return Ok(());
+        };
+        let mut outputs = self.outputs.lock().unwrap();
+        for output in outputs.iter_mut().filter(|output| output.xdg_output.is_none()) {
+            let wl_output = output.wl_output;
+            let id = self.new_object(move |client, _, event| client.xdg_output_event(wl_output, event));
+            self.send(manager, zxdg_output_manager_v1::Request::GetXdgOutput { id, output: wl_output })?;
+            output.xdg_output = Some(id);
+        }
+        Ok(())
+    }
+
+    fn with_output(&self, wl_output: u32, f: impl FnOnce(&mut Output)) {
+        if let Some(output) = self.outputs.lock().unwrap().iter_mut().find(|output| output.wl_output == wl_output) {
+            f(output);
+        }
+    }
+
+    pub fn wl_output_event(&self, wl_output: u32, event: wl_output::Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            // xdg_output's logical geometry replaces these when it is there
+            wl_output::Event::Geometry { x, y, .. } => self.with_output(wl_output, |output| {
+                if output.xdg_output.is_none() {
+                    (output.x, output.y) = (x, y);
+                }
+            }),
+            wl_output::Event::Mode { flags, width, height, .. } if flags & wl_output::Mode::Current as u32 > 0 => {
+                self.with_output(wl_output, |output| if output.xdg_output.is_none() {
+                    (output.width, output.height) = (width, height);
+                });
+            }
+            wl_output::Event::Mode { .. } => {}
+            wl_output::Event::Scale { factor } => self.with_output(wl_output, |output| output.scale = factor),
+            wl_output::Event::Name { name } => self.with_output(wl_output, |output| output.name = Some(name)),
+            wl_output::Event::Description { description } => {
+                self.with_output(wl_output, |output| output.description = Some(description));
+            }
+            wl_output::Event::Done => return self.wl_output_done(wl_output),
+        }
+        Ok(())
+    }
+
+    pub fn xdg_output_event(&self, wl_output: u32, event: zxdg_output_v1::Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            zxdg_output_v1::Event::LogicalPosition { x, y } => self.with_output(wl_output, |output| (output.x, output.y) = (x, y)),
+            zxdg_output_v1::Event::LogicalSize { width, height } => {
+                self.with_output(wl_output, |output| (output.width, output.height) = (width, height));
+            }
+            // wl_output v4 has the same, whichever comes last wins
+            zxdg_output_v1::Event::Name { name } => self.with_output(wl_output, |output| output.name = Some(name)),
+            zxdg_output_v1::Event::Description { description } => {
+                self.with_output(wl_output, |output| output.description = Some(description));
+            }
+            zxdg_output_v1::Event::Done => {}
+        }
+        Ok(())
+    }
+
+    /// An output finished describing itself, a new one gets a launcher if every output should
+    /// have one or if it is the named output the launcher couldn't open on
+    fn wl_output_done(&self, wl_output: u32) -> Result<(), Box<dyn Error>> {
+        let mut added = None;
+        self.with_output(wl_output, |output| {
+            if !output.ready {
+                output.ready = true;
+                added = Some(output.clone());
+            }
+        });
+        let Some(output) = added else {
+            return Ok(());
+        };
+        println!(
+            "Output {}:\n\tname: {}\n\tdescription: {}\n\tgeometry: {}x{} at {},{}\n\tscale: {}",
+            output.wl_output,
+            output.name.as_deref().unwrap_or("?"),
+            output.description.as_deref().unwrap_or("?"),
+            output.width,
+            output.height,
+            output.x,
+            output.y,
+            output.scale,
+        );
+
+        // Outputs there at startup are handled by init_toplevel
+        if !self.started.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        match &self.config.placement.output {
+            OutputChoice::All => {}
+            // Without layer shell there is no output to move to
+            OutputChoice::Named(name) if output.name.as_ref() == Some(name)
+                && self.global(&zwlr_layer_shell_v1::INTERFACE).is_ok() =>
+            {
+                // Surfaces without an output went where the compositor put them, as this one was missing
+                let fallbacks: Vec<u32> = self.surfaces.lock().unwrap().iter()
+                    .filter(|surface| surface.output.is_none())
+                    .map(|surface| surface.wl_surface)
+                    .collect();
+                println!("Output {} appeared, moving the launcher to it", name);
+                for wl_surface in fallbacks {
+                    self.destroy_surface(wl_surface)?;
+                }
+            }
+            _ => return Ok(()),
+        }
+        self.init_surface(Some(wl_output))?;
+        self.flush()
+    }
+
+    /// Outputs the launcher opens on, None lets the compositor pick
+    pub fn chosen_outputs(&self) -> Vec<Option<u32>> {
+        let outputs = self.outputs.lock().unwrap();
+        match &self.config.placement.output {
+            OutputChoice::Focused => vec![None],
+            OutputChoice::All => outputs.iter().filter(|output| output.ready).map(|output| Some(output.wl_output)).collect(),
+            OutputChoice::Named(name) => match outputs.iter().find(|output| output.name.as_ref() == Some(name)) {
+                Some(output) => vec![Some(output.wl_output)],
+                None => {
+                    println!("No output named {}, letting the compositor pick one", name);
+                    vec![None]
+                }
+            },
+        }
+    }
+
+    /// An output was unplugged, close the launchers on it and forget it
+    pub fn wl_output_remove(&self, global: u32) -> Result<(), Box<dyn Error>> {
+        let mut outputs = self.outputs.lock().unwrap();
+        let Some(index) = outputs.iter().position(|output| output.global == global) else {
+            return Ok(());
+        };
+        let output = outputs.remove(index);
+        drop(outputs);
+        println!("Output {} removed", output.name.as_deref().unwrap_or("?"));
+
+        let on_output: Vec<u32> = self.surfaces.lock().unwrap().iter()
+            .filter(|surface| surface.output == Some(output.wl_output))
+            .map(|surface| surface.wl_surface)
+            .collect();
+        for wl_surface in on_output {
+            self.destroy_surface(wl_surface)?;
+        }
+
+        if let Some(xdg_output) = output.xdg_output {
+            self.send(xdg_output, zxdg_output_v1::Request::Destroy)?;
+        }
+        // Release is new in version 3, older outputs just stay around unused
+        if self.objects.lock().unwrap().get(output.wl_output).is_some_and(|object| object.version >= 3) {
+            self.send(output.wl_output, wl_output::Request::Release)?;
+        }
+        self.flush()
+    }
+}
@@ -0,0 +1,167 @@
+use std::error::Error;
+
+use crate::{graphics::drawable::Drawable, wayland::{protocol::{wl_pointer, wl_seat}, wl_client::WlClient}};
+
+/// evdev code of the primary mouse button
+pub(crate) const BTN_LEFT: u32 = 0x110;
+/// Logical distance one wheel notch scrolls
+const SCROLL_STEP: f64 = 40.0;
+
+/// Pointer events are collected here until wl_pointer.frame and handled together
+#[derive(Debug, Default)]
+pub struct Pointer {
+    /// Surface the pointer is over, x and y are logical on it
+    pub surface:    Option<u32>,
+    pub x:          f64,
+    pub y:          f64,
+    /// Drawable under the pointer as surface and index
+    pub hovered:    Option<(u32, usize)>,
+    /// Drawable the primary button went down on, a click if it also goes up on it
+    pub pressed:    Option<(u32, usize)>,
+    /// Button and state of the frame's button events
+    pub buttons:    Vec<(u32, u32)>,
+    /// Scroll of the frame in surface units, indexed by wl_pointer::Axis
+    pub scroll:     [f64; 2],
+    /// Wheel scroll of the frame in 120ths of a notch, used over scroll for its axis
+    pub scroll120:  [Option<i32>; 2],
+}
+
+impl WlClient {
+    pub fn wl_seat_get_pointer(&self, seat: u32) -> Result<u32, Box<dyn Error>> {
+        let id = self.new_object(|client, _, event| client.wl_pointer_event(event));
+        self.send(seat, wl_seat::Request::GetPointer { id })?;
+        Ok(id)
+    }
+
+    pub fn wl_pointer_event(&self, event: wl_pointer::Event) -> Result<(), Box<dyn Error>> {
+        {
+            let mut pointer = self.pointer.lock().unwrap();
+            match event {
+                wl_pointer::Event::Enter { surface, surface_x, surface_y, .. } => {
+                    println!("Pointer entered surface {}", surface);
+                    pointer.surface = Some(surface);
+                    (pointer.x, pointer.y) = (surface_x.to_f64(), surface_y.to_f64());
+                }
+                wl_pointer::Event::Leave { surface, .. } => {
+                    println!("Pointer left surface {}", surface);
+                    pointer.surface = None;
+                }
+                wl_pointer::Event::Motion { surface_x, surface_y, .. } => {
+                    (pointer.x, pointer.y) = (surface_x.to_f64(), surface_y.to_f64());
+                }
+                wl_pointer::Event::Button { button, state, .. } => pointer.buttons.push((button, state)),
+                wl_pointer::Event::Axis { axis, value, .. } => {
+                    if let Some(scroll) = pointer.scroll.get_mut(axis as usize) {
+                        *scroll += value.to_f64();
+                    }
+                }
+                // Only sent before version 8, axis_value120 replaces it
+                wl_pointer::Event::AxisDiscrete { axis, discrete } => {
+                    if let Some(scroll) = pointer.scroll120.get_mut(axis as usize) {
+                        *scroll = Some(scroll.unwrap_or(0) + discrete * 120);
+                    }
+                }
+                wl_pointer::Event::AxisValue120 { axis, value120 } => {
+                    if let Some(scroll) = pointer.scroll120.get_mut(axis as usize) {
+                        *scroll = Some(scroll.unwrap_or(0) + value120);
+                    }
+                }
+                wl_pointer::Event::Frame => {
+                    drop(pointer);
+                    return self.wl_pointer_frame();
+                }
+                _ => {}
+            }
+        }
+        // Before version 5 there are no frames, every event stands on its own
+        if self.global_version(&wl_seat::INTERFACE) < 5 {
+            self.wl_pointer_frame()?;
+        }
+        Ok(())
+    }
+
+    /// Handle the events of a frame, the drawable under the pointer is hovered and gets
+    /// the frame's clicks and scrolling
+    fn wl_pointer_frame(&self) -> Result<(), Box<dyn Error>> {
+        // Nothing else is locked while the drawables react
+        let (hit, unhover, hover, click, dx, dy) = {
+            let mut pointer = self.pointer.lock().unwrap();
+            let pointer = &mut *pointer;
+            let hit = pointer.surface.and_then(|wl_surface| self.drawable_at(wl_surface, pointer.x, pointer.y));
+            let (unhover, hover) = match pointer.hovered == hit {
+                true => (None, None),
+                false => (pointer.hovered, hit),
+            };
+            pointer.hovered = hit;
+
+            let mut click = None;
+            for (_, state) in pointer.buttons.drain(..).filter(|(button, _)| *button == BTN_LEFT) {
+                if state == wl_pointer::ButtonState::Pressed as u32 {
+                    pointer.pressed = hit;
+                } else {
+                    click = pointer.pressed.take().filter(|pressed| Some(*pressed) == hit);
+                }
+            }
+            // Releases off our surfaces are never seen
+            if pointer.surface.is_none() {
+                pointer.pressed = None;
+            }
+
+            let [dy, dx] = [0, 1].map(|axis| match pointer.scroll120[axis].take() {
+                Some(value120) => value120 as f64 / 120.0 * SCROLL_STEP,
+                None => pointer.scroll[axis],
+            });
+            pointer.scroll = [0.0; 2];
+            (hit, unhover, hover, click, dx, dy)
+        };
+
+        if let Some(target) = unhover {
+            self.drawable_input(target, |drawable| drawable.hover(false))?;
+        }
+        if let Some(target) = hover {
+            self.drawable_input(target, |drawable| drawable.hover(true))?;
+        }
+        if let Some(target) = hit && (dx != 0.0 || dy != 0.0) {
+            self.drawable_input(target, |drawable| drawable.scroll(dx, dy))?;
+        }
+        if let Some(target) = click {
+            let mut command = None;
+            self.drawable_input(target, |drawable| {
+                command = drawable.click();
+                false
+            })?;
+            if let Some(command) = command {
+                self.launch(&command)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drawable of a surface at logical x, y as surface and index
+    pub fn drawable_at(&self, wl_surface: u32, x: f64, y: f64) -> Option<(u32, usize)> {
+        // The last drawable is drawn on top, so it is hit first
+        self.drawables.lock().unwrap()
+            .get(&wl_surface)?
+            .iter()
+            .rposition(|drawable| drawable.bounds().contains(x, y))
+            .map(|index| (wl_surface, index))
+    }
+
+    /// Pass input to a drawable, where it was and is gets drawn again if input reports
+    /// it looks different
+    pub fn drawable_input(&self, (wl_surface, index): (u32, usize), input: impl FnOnce(&mut dyn Drawable) -> bool) -> Result<(), Box<dyn Error>> {
+        let damage = {
+            let mut drawables = self.drawables.lock().unwrap();
+            // The scene may have been replaced since
+            let Some(drawable) = drawables.get_mut(&wl_surface).and_then(|drawables| drawables.get_mut(index)) else {
+                return Ok(());
+            };
+            let before = drawable.bounds();
+            if !input(drawable.as_mut()) {
+                return Ok(());
+            }
+            [before, drawable.bounds()]
+        };
+        self.surface_damage(wl_surface, &damage)
+    }
+}
@@ -0,0 +1,90 @@
+// Protocol bindings generated by build.rs from the XML files in protocols/
+// Every interface gets a module with its descriptor, a Request and an Event enum
+// and one Rust enum per protocol enum
+
+use std::{collections::VecDeque, os::fd::OwnedFd};
+
+pub use crate::wayland::vec_utils::{array_size, string_size, DecodeErr, WlMessage};
+
+/// Signed 24.8 fixed point number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fixed (pub i32);
+
+impl Fixed {
+    #[cfg(test)]
+    pub fn from_f64(value: f64) -> Self {
+        Fixed((value * 256.0).round() as i32)
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / 256.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    Int,
+    Uint,
+    Fixed,
+    String,
+    Object,
+    NewId,
+    Array,
+    Fd,
+}
+
+#[derive(Debug)]
+pub struct ArgDesc {
+    pub kind:       ArgKind,
+    pub interface:  Option<&'static str>,
+}
+
+#[derive(Debug)]
+pub struct MessageDesc {
+    pub name:       &'static str,
+    /// The object is gone once this request is sent, the server confirms with delete_id
+    pub destructor: bool,
+    pub args:       &'static [ArgDesc],
+}
+
+impl MessageDesc {
+    /// Number of file descriptors the message carries
+    pub fn fd_count(&self) -> usize {
+        self.args.iter().filter(|arg| arg.kind == ArgKind::Fd).count()
+    }
+}
+
+#[derive(Debug)]
+pub struct Interface {
+    pub name:       &'static str,
+    pub version:    u32,
+    pub requests:   &'static [MessageDesc],
+    pub events:     &'static [MessageDesc],
+}
+
+/// A request or event of one interface
+pub trait Message: Sized {
+    const INTERFACE: &'static Interface;
+    fn opcode(&self) -> u16;
+    /// Serialize into a message for object, fd arguments are moved into fds
+    fn encode(self, object: u32, fds: &mut Vec<OwnedFd>) -> Vec<u8>;
+    /// Parse a message body (without header), fd arguments are taken from fds in order
+    fn decode(opcode: u16, body: &[u8], fds: &mut VecDeque<OwnedFd>) -> Result<Self, DecodeErr>;
+}
+
+/// Interface descriptor by protocol name
+#[cfg(test)]
+pub fn find_interface(name: &str) -> Option<&'static Interface> {
+    INTERFACES.iter().find(|interface| interface.name == name).copied()
+}
+
+fn take_fd(fds: &mut VecDeque<OwnedFd>) -> Result<OwnedFd, DecodeErr> {
+    fds.pop_front().ok_or(DecodeErr("missing file descriptor".to_string()))
+}
+
+#[allow(dead_code, unused, non_camel_case_types, clippy::all)]
+mod generated {
+    use super::*;
+    include!(concat!(env!("OUT_DIR"), "/protocols.rs"));
+}
+pub use generated::*;
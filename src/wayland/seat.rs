@@ -1,82 +1,129 @@
-#![feature(unix_socket_ancillary_data)]
+use std::{error::Error, os::fd::OwnedFd, time::Duration};
 
-use std::{error::Error, io::{IoSliceMut, Write}, os::unix::net::{AncillaryData, SocketAncillary}, sync::atomic::Ordering};
-
-use crate::wayland::{shm, vec_utils::WlMessage, wl_client::WlClient, surface::UnsetErr};
+use crate::wayland::{protocol::{wl_keyboard, wl_seat}, shm, wl_client::WlClient};
 
 use super::xkb;
 
+#[derive(Debug, Default)]
+pub struct KeyRepeat {
+    pub rate:   i32, // keys per second, 0 leaves repeating to the compositor
+    pub delay:  i32, // ms
+    pub timer:  Option<u64>,
+    pub key:    Option<u32>,
+}
 
 impl WlClient {
-    pub fn wl_seat_capabilities(&self, event: &Vec<u8>) -> Result<(), Box<dyn Error>> {
-        let mut offset: usize = 0;
-        let capability = event.read_u32(&mut offset);
+    pub fn wl_seat_event(&self, seat: u32, event: wl_seat::Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            wl_seat::Event::Capabilities { capabilities } => self.wl_seat_capabilities(seat, capabilities),
+            wl_seat::Event::Name { name } => {
+                println!("Recieved seat name: {}", name);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn wl_seat_capabilities(&self, seat: u32, capability: u32) -> Result<(), Box<dyn Error>> {
         println!(
             "Received seat capabilities:\n\tPointer: {}\n\tKeyboard: {}\n\tTouch: {}",
-            (capability & 1) > 0,
-            (capability & 2) > 0,
-            (capability & 4) > 0,
+            (capability & wl_seat::Capability::Pointer as u32) > 0,
+            (capability & wl_seat::Capability::Keyboard as u32) > 0,
+            (capability & wl_seat::Capability::Touch as u32) > 0,
         );
-        if (capability & 2) > 0 {
-            self.wl_seat_get_keyboard()?;
+        if (capability & wl_seat::Capability::Keyboard as u32) > 0 {
+            self.wl_seat_get_keyboard(seat)?;
+        }
+        if (capability & wl_seat::Capability::Pointer as u32) > 0 {
+            self.wl_seat_get_pointer(seat)?;
+        }
+        if (capability & wl_seat::Capability::Touch as u32) > 0 {
+            self.wl_seat_get_touch(seat)?;
         }
 
         Ok(())
     }
 
-    pub fn wl_seat_name(&self, event: &Vec<u8>) {
-        let mut offset: usize = 0;
-        let name = event.read_string(&mut offset);
-        println!("Recieved seat name: {}", name);
-    }
+    pub fn wl_seat_get_keyboard(&self, seat: u32) -> Result<u32, Box<dyn Error>> {
+        let id = self.new_object(|client, _, event| client.wl_keyboard_event(event));
+        self.send(seat, wl_seat::Request::GetKeyboard { id })?;
 
-    pub fn wl_seat_get_keyboard(&self) -> Result<(), Box<dyn Error>> {
-        let object = self.seat_id.load(Ordering::Relaxed);
-        if object == 0 {
-            return Err(UnsetErr("keyboard_id".to_string()).into())
+        let mut key_repeat = self.key_repeat.lock().unwrap();
+        if key_repeat.timer.is_none() {
+            key_repeat.timer = Some(self.event_loop.lock().unwrap().add_timer(|client| client.wl_keyboard_repeat())?);
         }
-        const OPCODE: u16 = 1;
-        const MSG_SIZE: u16 = 12;
-
-        let mut request = vec![0u8; MSG_SIZE as usize];
-        let mut offset: usize = 0;
-        request.write_u32(&object, &mut offset);
-        request.write_u16(&OPCODE, &mut offset);
-        request.write_u16(&MSG_SIZE, &mut offset);
+        Ok(id)
+    }
 
-        let current_id = self.current_id.fetch_add(1, Ordering::Relaxed) + 1;
-        self.keyboard_id.store(current_id, Ordering::Relaxed);
-        request.write_u32(&current_id, &mut offset);
+    pub fn wl_keyboard_event(&self, event: wl_keyboard::Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            wl_keyboard::Event::Keymap { fd, size, .. } => self.wl_keyboard_keymap(fd, size)?,
+            wl_keyboard::Event::Enter { surface, .. } => {
+                println!("Keyboard focus entered surface {}", surface);
+                *self.keyboard_focus.lock().unwrap() = Some(surface);
+            }
+            wl_keyboard::Event::Leave { surface, .. } => {
+                println!("Keyboard focus left surface {}", surface);
+                *self.keyboard_focus.lock().unwrap() = None;
+                self.wl_keyboard_set_repeat(None, 0)?;
+            }
+            // Keys without focus are stale, e.g. a release racing a leave
+            wl_keyboard::Event::Key { .. } if self.keyboard_focus.lock().unwrap().is_none() => {}
+            wl_keyboard::Event::Key { serial, time, key, state } => {
+                self.wl_keyboard_key(serial, time, key, state)?;
+                self.wl_keyboard_set_repeat(Some(key), state)?;
+            }
+            wl_keyboard::Event::RepeatInfo { rate, delay } => {
+                let mut key_repeat = self.key_repeat.lock().unwrap();
+                key_repeat.rate = rate;
+                key_repeat.delay = delay;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
 
-        self.socket.lock().unwrap().write(&request)?;
-        
+    /// Start repeating key on press, stop when it is released or focus is lost (key None)
+    fn wl_keyboard_set_repeat(&self, key: Option<u32>, state: u32) -> Result<(), Box<dyn Error>> {
+        let mut key_repeat = self.key_repeat.lock().unwrap();
+        let Some(timer) = key_repeat.timer else {
+            return Ok(());
+        };
+        if key.is_some() && state == wl_keyboard::KeyState::Pressed as u32 && key_repeat.rate > 0 {
+            key_repeat.key = key;
+            self.event_loop.lock().unwrap().set_timer(
+                timer,
+                Some(Duration::from_millis(key_repeat.delay.max(0) as u64)),
+                Some(Duration::from_secs(1) / key_repeat.rate as u32),
+            )?;
+        } else if key.is_none() || key == key_repeat.key && state == wl_keyboard::KeyState::Released as u32 {
+            key_repeat.key = None;
+            self.event_loop.lock().unwrap().set_timer(timer, None, None)?;
+        }
         Ok(())
     }
 
-    pub fn wl_keyboard_keymap(&self, event: &Vec<u8>, fd: i32) -> Result<(), Box<dyn Error>>{
-        let mut offset: usize = 0;
-        let format = event.read_u32(&mut offset);
-        let size = event.read_u32(&mut offset);
+    fn wl_keyboard_repeat(&self) -> Result<(), Box<dyn Error>> {
+        let key = self.key_repeat.lock().unwrap().key;
+        match key {
+            Some(key) => self.wl_keyboard_key(0, 0, key, wl_keyboard::KeyState::Pressed as u32),
+            None => Ok(()),
+        }
+    }
 
+    pub fn wl_keyboard_keymap(&self, fd: OwnedFd, size: u32) -> Result<(), Box<dyn Error>>{
         let mut keymap_fd = self.keymap_fd.lock().unwrap();
-        *keymap_fd = Some(shm::ShmPool::from_fd(fd, size as usize)?);
+        *keymap_fd = Some(shm::ShmMap::new(fd, size as usize)?);
         let mut keymap = self.keymap.write().unwrap();
         *keymap = xkb::gen_id_keysym_mapping(keymap_fd.as_ref().unwrap());
 
         Ok(())
     }
 
-    pub fn wl_keyboard_key(&self, event: &Vec<u8>) -> Result<(), Box<dyn Error>> {
-        let mut offset: usize = 0;
-        let serial = event.read_u32(&mut offset);
-        let time = event.read_u32(&mut offset);
-        let key = event.read_u32(&mut offset);
-        let state = event.read_u32(&mut offset);
-
+    pub fn wl_keyboard_key(&self, serial: u32, time: u32, key: u32, state: u32) -> Result<(), Box<dyn Error>> {
         if let Some(keymap) = &*self.keymap.read().unwrap() {
             if let Some(keysym) = keymap.get(&(key + 8)) {
-                if keysym[0] == "Escape" && state == 0 {
-                    self.exit();
+                if keysym[0] == "Escape" && state == wl_keyboard::KeyState::Released as u32 {
+                    self.exit()?;
                 }
                 println!("Received key:\n\t{} {}", keysym[0], if state == 0 {'↑'} else {'↓'});
             } else {
@@ -1,37 +1,33 @@
-use libc::{c_void, ftruncate, mmap, munmap, shm_open, shm_unlink, MAP_FAILED, MAP_PRIVATE, MAP_SHARED, O_CREAT, O_EXCL, O_RDWR, PROT_READ, PROT_WRITE};
+use std::{ffi::CString, io, os::fd::{AsRawFd, FromRawFd, OwnedFd}, sync::atomic::{AtomicU32, Ordering}, time::{SystemTime, UNIX_EPOCH}};
 
-use crate::graphics::drawable::color_over;
+use libc::{c_void, fcntl, ftruncate, getpid, memfd_create, mmap, munmap, shm_open, shm_unlink, EEXIST, F_ADD_SEALS, F_SEAL_SHRINK, MAP_FAILED, MAP_PRIVATE, MAP_SHARED, MFD_ALLOW_SEALING, MFD_CLOEXEC, O_CREAT, O_EXCL, O_RDWR, PROT_READ, PROT_WRITE};
 
-#[derive(Clone)]
+/// Names tried before giving up on shm_open
+const SHM_OPEN_TRIES: usize = 100;
+
+#[derive(Debug)]
 pub struct ShmPool {
-    pub fd:     i32,
+    pub fd:     OwnedFd,
     pub addr:   *mut c_void,
     pub size:   usize,
 }
 
 impl ShmPool {
-    pub fn new(size: usize) -> std::io::Result<ShmPool> {
-        let shm_path: *const i8 = b"/chlorostart\0".as_ptr() as *const i8;
-        let fd = unsafe { shm_open(shm_path, O_RDWR | O_EXCL | O_CREAT, 0o600) };
-        if fd == -1 {
-            eprint!("shm_open in ShmPool::new() failed: ");
-            return Err(std::io::Error::last_os_error())
-        }
-        if unsafe { shm_unlink(shm_path) } == -1 {
-            eprint!("shm_unlink in ShmPool::new() failed: ");
-            return Err(std::io::Error::last_os_error())
-        }
-        if unsafe { ftruncate(fd, size as i64) } == -1 {
-            eprint!("ftruncate in ShmPool::new() failed: ");
-            return Err(std::io::Error::last_os_error())
+    pub fn new(size: usize) -> io::Result<ShmPool> {
+        let fd = Self::create_fd()?;
+        if unsafe { ftruncate(fd.as_raw_fd(), size as i64) } == -1 {
+            let err = io::Error::last_os_error();
+            eprintln!("ftruncate in ShmPool::new() failed: {}", err);
+            return Err(err)
         }
         let addr = unsafe {
-            mmap(std::ptr::null_mut(), size, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0)
+            mmap(std::ptr::null_mut(), size, PROT_READ | PROT_WRITE, MAP_SHARED, fd.as_raw_fd(), 0)
         };
 
         if addr == MAP_FAILED {
-            eprint!("mmap in ShmPool::new() failed: ");
-            return Err(std::io::Error::last_os_error())
+            let err = io::Error::last_os_error();
+            eprintln!("mmap in ShmPool::new() failed: {}", err);
+            return Err(err)
         }
 
         Ok(ShmPool {
@@ -41,90 +37,128 @@ impl ShmPool {
         })
     }
 
-    pub fn from_fd(fd: i32, size: usize) -> std::io::Result<ShmPool> {
-        let addr = unsafe { mmap(std::ptr::null_mut(), size, PROT_READ, MAP_PRIVATE, fd, 0) };
-        if addr == MAP_FAILED {
-            eprint!("mmap in ShmPool::from_fd() failed with fd {}", fd);
-            return Err(std::io::Error::last_os_error());
+    // memfd needs no /dev/shm, which sandboxes and test runners often lack
+    fn create_fd() -> io::Result<OwnedFd> {
+        let fd = unsafe { memfd_create(c"chlorostart".as_ptr(), MFD_CLOEXEC | MFD_ALLOW_SEALING) };
+        if fd != -1 {
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            // The compositor maps the pool too, a pool that can't shrink can't make it fault
+            if unsafe { fcntl(fd.as_raw_fd(), F_ADD_SEALS, F_SEAL_SHRINK) } != -1 {
+                return Ok(fd);
+            }
+            let err = io::Error::last_os_error();
+            eprintln!("fcntl in ShmPool::new() failed, falling back to shm_open: {}", err);
         }
-        Ok(ShmPool {fd, addr, size})
+        Self::shm_open_unique()
     }
 
-    pub fn read_string(&self, offset: usize) -> std::io::Result<String> {
-        let mut res: Vec<u8> = Vec::new();
-        for i in offset..self.size {
-            let byte = unsafe {*(self.addr.offset(i as isize) as *const u8)};
-            if byte == 0 {
-                break;
+    /// shm_open under a fresh name, unlinked right away so nothing is left behind
+    fn shm_open_unique() -> io::Result<OwnedFd> {
+        for _ in 0..SHM_OPEN_TRIES {
+            let shm_path = unique_name();
+            let fd = unsafe { shm_open(shm_path.as_ptr(), O_RDWR | O_EXCL | O_CREAT, 0o600) };
+            if fd == -1 {
+                let err = io::Error::last_os_error();
+                // Another instance holds the name, O_EXCL keeps us off its pool
+                if err.raw_os_error() == Some(EEXIST) {
+                    continue;
+                }
+                eprintln!("shm_open in ShmPool::new() failed: {}", err);
+                return Err(err)
             }
-            res.push(byte);
-        };
-        Ok(String::from_utf8(res).unwrap())
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            if unsafe { shm_unlink(shm_path.as_ptr()) } == -1 {
+                let err = io::Error::last_os_error();
+                eprintln!("shm_unlink in ShmPool::new() failed: {}", err);
+                return Err(err)
+            }
+            return Ok(fd);
+        }
+        Err(io::Error::new(io::ErrorKind::AlreadyExists, "no free shm_open name"))
     }
 
-    pub fn resize(&mut self, size: usize) -> std::io::Result<()> {
-        if unsafe { ftruncate(self.fd, size as i64) } == -1 {
-            return Err(std::io::Error::last_os_error())
+    /// Grow the file to size bytes and map it again, pixels already written are kept
+    pub fn resize(&mut self, size: usize) -> io::Result<()> {
+        if unsafe { ftruncate(self.fd.as_raw_fd(), size as i64) } == -1 {
+            let err = io::Error::last_os_error();
+            eprintln!("ftruncate in ShmPool::resize() failed: {}", err);
+            return Err(err)
+        }
+        let addr = unsafe {
+            mmap(std::ptr::null_mut(), size, PROT_READ | PROT_WRITE, MAP_SHARED, self.fd.as_raw_fd(), 0)
         };
+        if addr == MAP_FAILED {
+            let err = io::Error::last_os_error();
+            eprintln!("mmap in ShmPool::resize() failed: {}", err);
+            return Err(err)
+        }
+        unsafe { munmap(self.addr, self.size); }
+        self.addr = addr;
         self.size = size;
-
         Ok(())
     }
 
-    pub fn write(&mut self, color: u32, offset: usize, len: usize) {
-        if offset + len/4 >= self.size {
-            return;
-        }
-        for i in offset..offset+len { unsafe {
-            *((self.addr as *mut u32).offset(i as isize))
-                = color_over(color, self.read_pixel(i).unwrap());
-        }}
+    /// The whole mapping as 32 bit pixels, a trailing partial pixel is left out
+    pub fn pixels_mut(&mut self) -> &mut [u32] {
+        // mmap returns page aligned memory that stays mapped until self is dropped or resized
+        unsafe { std::slice::from_raw_parts_mut(self.addr as *mut u32, self.size / 4) }
     }
+}
 
-    pub fn write_raw(&mut self, color: u32, offset: usize, len: usize) {
-        if offset + len/4 >= self.size {
-            return;
-        }
-        for i in offset..offset+len { unsafe {
-            *((self.addr as *mut u32).offset(i as isize)) = color;
-        }}
+impl Drop for ShmPool {
+    fn drop(&mut self) {
+        unsafe { munmap(self.addr, self.size); }
     }
+}
 
-    pub fn write_pixel(&mut self, color: u32, offset: usize) {
-        // TODO: Return error if out of bounds
-        if offset + 3 > self.size {
-            return;
-        }
-        unsafe {
-            *((self.addr as *mut u32).offset(offset as isize)) 
-            = color_over(color, self.read_pixel(offset).unwrap());
-        }
-    }
+/// Name for shm_open that is unlikely to be taken, from the pid, the time and a counter
+fn unique_name() -> CString {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+    let mut seed = (unsafe { getpid() } as u64) << 32 ^ nanos as u64 ^ (COUNTER.fetch_add(1, Ordering::Relaxed) as u64) << 48;
+    // xorshift spreads the bits over the letters
+    let suffix: String = (0..8).map(|_| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        (b'a' + (seed % 26) as u8) as char
+    }).collect();
+    CString::new(format!("/chlorostart-{}", suffix)).unwrap()
+}
 
-    pub fn write_pixel_raw(&mut self, color: u32, offset: usize) {
-        // TODO: Return error if out of bounds
-        if offset + 3 > self.size {
-            return;
-        }
-        unsafe {
-            *((self.addr as *mut u32).offset(offset as isize)) = color;
+unsafe impl Send for ShmPool {}
+unsafe impl Sync for ShmPool {}
+
+/// Read only private mapping of a file the compositor sent, e.g. a keymap
+#[derive(Debug)]
+pub struct ShmMap {
+    addr:   *mut c_void,
+    size:   usize,
+}
+
+impl ShmMap {
+    /// Map size bytes of fd, the fd is closed as the mapping doesn't need it
+    pub fn new(fd: OwnedFd, size: usize) -> io::Result<ShmMap> {
+        let addr = unsafe { mmap(std::ptr::null_mut(), size, PROT_READ, MAP_PRIVATE, fd.as_raw_fd(), 0) };
+        if addr == MAP_FAILED {
+            let err = io::Error::last_os_error();
+            eprintln!("mmap in ShmMap::new() failed with fd {}: {}", fd.as_raw_fd(), err);
+            return Err(err);
         }
+        Ok(ShmMap {addr, size})
     }
 
-    pub fn read_pixel(&self, offset: usize) -> Option<u32> {
-        if offset + 3 > self.size {
-            return None;
-        }
-        return Some(unsafe {*(self.addr.offset(4*offset as isize) as *const u32)});
+    pub fn bytes(&self) -> &[u8] {
+        // Mapped until self is dropped, nothing writes to a private mapping
+        unsafe { std::slice::from_raw_parts(self.addr as *const u8, self.size) }
     }
 }
 
-impl Drop for ShmPool {
+impl Drop for ShmMap {
     fn drop(&mut self) {
-        println!("Dropping ShmPool!");
         unsafe { munmap(self.addr, self.size); }
     }
 }
 
-unsafe impl Send for ShmPool {}
-unsafe impl Sync for ShmPool {}
+unsafe impl Send for ShmMap {}
+unsafe impl Sync for ShmMap {}
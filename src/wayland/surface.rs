@@ -1,6 +1,6 @@
-use std::{error::Error, io::Write, sync::atomic::Ordering};
+use std::error::Error;
 
-use crate::wayland::{vec_utils::WlMessage, wl_client::WlClient, wl_shm::wl_buffer};
+use crate::{graphics::{damage::{add_damage, Rect}, drawable::{scaled, Drawable}, renderer::{redraw, update}}, wayland::{protocol::{wl_buffer, wl_callback, wl_compositor, wl_shm_pool, wl_surface, xdg_surface, wp_fractional_scale_v1, wp_viewport, xdg_toplevel, zwlr_layer_surface_v1}, shm::ShmPool, swapchain::Swapchain, wl_client::WlClient, wl_shm::{ShmCanvas, WlBuffer}}};
 
 use std::fmt;
 
@@ -14,142 +14,371 @@ impl fmt::Display for UnsetErr {
     }
 }
 
+/// Buffers a surface starts with, more are added while the compositor holds all of them
+pub const BUFFER_COUNT: usize = 2;
+
+/// Role objects that make a wl_surface a window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Layer { layer_surface: u32 },
+    /// Fallback for compositors without layer shell
+    Toplevel { xdg_surface: u32, xdg_toplevel: u32 },
+}
+
+/// A launcher window: wl_surface, its role object and the buffers drawn into it
+#[derive(Debug)]
+pub struct Surface {
+    pub wl_surface:     u32,
+    pub role:           Role,
+    /// wl_output the surface was put on, None when the compositor picked
+    pub output:         Option<u32>,
+    pub shm_pool:       ShmPool,
+    pub wl_shm_pool:    u32,
+    pub swapchain:      Swapchain,
+    /// Logical size, buffers are scale times larger
+    pub width:          usize,
+    pub height:         usize,
+    /// Pixels per logical pixel, fractional only with a viewport
+    pub scale:          f64,
+    /// wp_fractional_scale_v1 and wp_viewport, buffer scale stays 1 while they are used
+    pub fractional:     Option<(u32, u32)>,
+    /// Outputs the surface is shown on, their scale is used without preferred_buffer_scale
+    pub entered:        Vec<u32>,
+    /// Size of the last xdg_toplevel.configure, applied on xdg_surface.configure
+    pub pending_size:   (u32, u32),
+    pub frame_callback: Option<u32>,
+    /// A frame was asked for while the last one was still on its way
+    pub redraw:         bool,
+    /// Pixels changed since the last commit
+    pub damage:         Vec<Rect>,
+}
+
 impl WlClient {
-    pub fn wl_compositor_create_surface(&self) -> Result<(), Box<dyn Error>> {
-        let object = self.compositor_id.load(Ordering::Relaxed);
-        if object == 0 {
-            return Err(UnsetErr("compositor_id".to_string()).into());
-        }
-        const OPCODE: u16 = 0;
-        const MSG_SIZE: u16 = 12;
+    pub fn wl_compositor_create_surface(&self) -> Result<u32, Box<dyn Error>> {
+        let object = self.global(&wl_compositor::INTERFACE)?;
+
+        let id = self.new_object(|client, id, event| client.wl_surface_event(id, event));
+        self.send(object, wl_compositor::Request::CreateSurface { id })?;
 
-        let mut request = vec![0u8; MSG_SIZE as usize];
-        let mut offset: usize = 0;
+        Ok(id)
+    }
 
-        request.write_u32(&object, &mut offset);
-        request.write_u16(&OPCODE, &mut offset);
-        request.write_u16(&MSG_SIZE, &mut offset);
+    pub fn wl_surface_event(&self, wl_surface: u32, event: wl_surface::Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            wl_surface::Event::PreferredBufferScale { factor } if !self.surface_is_fractional(wl_surface) => {
+                println!("Preferred buffer scale: {}", factor);
+                self.surface_set_scale(wl_surface, factor.max(1) as f64)?;
+            }
+            wl_surface::Event::PreferredBufferScale { .. } => {}
+            wl_surface::Event::PreferredBufferTransform { .. } => {
+                // println!("Preferred buffer transform: {}", transform);
+            }
+            wl_surface::Event::Enter { output } => self.wl_surface_outputs_changed(wl_surface, |entered| entered.push(output))?,
+            wl_surface::Event::Leave { output } => {
+                self.wl_surface_outputs_changed(wl_surface, |entered| entered.retain(|entered| *entered != output))?;
+            }
+        }
+        Ok(())
+    }
 
-        let current_id = self.current_id.fetch_add(1, Ordering::Relaxed) + 1;
-        request.write_u32(&current_id, &mut offset);
+    /// Before wl_surface v6 the scale is the largest of the outputs the surface is on
+    fn wl_surface_outputs_changed(&self, wl_surface: u32, f: impl FnOnce(&mut Vec<u32>)) -> Result<(), Box<dyn Error>> {
+        let mut surfaces = self.surfaces.lock().unwrap();
+        let Some(surface) = surfaces.iter_mut().find(|surface| surface.wl_surface == wl_surface) else {
+            return Ok(());
+        };
+        f(&mut surface.entered);
+        let entered = surface.entered.clone();
+        drop(surfaces);
+
+        if self.global_version(&wl_compositor::INTERFACE) >= 6 || self.surface_is_fractional(wl_surface) {
+            return Ok(());
+        }
+        let scale = self.outputs.lock().unwrap().iter()
+            .filter(|output| entered.contains(&output.wl_output))
+            .map(|output| output.scale.max(1))
+            .max()
+            .unwrap_or(1);
+        self.surface_set_scale(wl_surface, scale as f64)
+    }
 
-        self.socket.lock().unwrap().write(&request)?;
+    fn surface_is_fractional(&self, wl_surface: u32) -> bool {
+        self.surfaces.lock().unwrap().iter().any(|surface| surface.wl_surface == wl_surface && surface.fractional.is_some())
+    }
 
-        self.surface_id.store(current_id, Ordering::Relaxed);
+    /// Render at scale pixels per logical pixel from the next frame on, the scale
+    /// is whole unless the surface has a viewport
+    pub fn surface_set_scale(&self, wl_surface: u32, scale: f64) -> Result<(), Box<dyn Error>> {
+        let mut surfaces = self.surfaces.lock().unwrap();
+        let surface = surfaces.iter_mut()
+            .find(|surface| surface.wl_surface == wl_surface)
+            .ok_or(UnsetErr("surface".to_string()))?;
+        // set_buffer_scale is new in version 3
+        let fractional = surface.fractional.is_some();
+        if surface.scale == scale || !fractional && self.global_version(&wl_compositor::INTERFACE) < 3 {
+            return Ok(());
+        }
+        surface.scale = scale;
+        let (width, height, configured) = (surface.width, surface.height, !surface.swapchain.buffers.is_empty());
+        drop(surfaces);
 
+        // The viewport maps the buffer to the logical size instead
+        if !fractional {
+            self.send(wl_surface, wl_surface::Request::SetBufferScale { scale: scale as i32 })?;
+        }
+        // Before the first configure there is nothing to redraw, that configure uses the scale
+        if configured {
+            self.surface_resize(wl_surface, width, height)?;
+            self.wl_surface_frame(wl_surface)?;
+        }
         Ok(())
     }
 
-    pub fn wl_surface_attach(&self, buffer: &wl_buffer) -> Result<(), Box<dyn Error + '_>> {
+    pub fn wl_surface_attach(&self, wl_surface: u32, buffer: &WlBuffer) -> Result<(), Box<dyn Error>> {
+        self.send(wl_surface, wl_surface::Request::Attach { buffer: buffer.id, x: 0, y: 0 })
+    }
+
+    /// Damage in buffer pixels, before wl_surface v4 in surface coordinates instead
+    pub fn wl_surface_damage(&self, wl_surface: u32, damage: &[Rect], scale: f64) -> Result<(), Box<dyn Error>> {
+        let buffer_coordinates = self.global_version(&wl_compositor::INTERFACE) >= 4;
+        for rect in damage {
+            let rect = if buffer_coordinates { *rect } else { rect.unscaled(scale) };
+            let (x, y, width, height) = (rect.x as i32, rect.y as i32, rect.width as i32, rect.height as i32);
+            if buffer_coordinates {
+                self.send(wl_surface, wl_surface::Request::DamageBuffer { x, y, width, height })?;
+            } else {
+                self.send(wl_surface, wl_surface::Request::Damage { x, y, width, height })?;
+            }
+        }
+        Ok(())
+    }
 
-        let id = buffer.id;
+    pub fn wl_surface_commit(&self, wl_surface: u32) -> Result<(), Box<dyn Error>> {
+        self.send(wl_surface, wl_surface::Request::Commit)
+    }
 
-        let object = self.surface_id.load(Ordering::Relaxed);
-        if object == 0 {
-            return Err(UnsetErr("surface_id".to_string()).into());
+    /// Draw and commit the next frame now, a frame callback is only asked for while
+    /// something animates
+    pub fn wl_surface_frame(&self, wl_surface: u32) -> Result<(), Box<dyn Error>> {
+        let mut surfaces = self.surfaces.lock().unwrap();
+        let surface = surfaces.iter_mut()
+            .find(|surface| surface.wl_surface == wl_surface)
+            .ok_or(UnsetErr("surface".to_string()))?;
+        surface.redraw = false;
+
+        if surface.swapchain.buffers.is_empty() {
+            return Err(Box::new(UnsetErr("buffers".to_string())));
+        }
+        // Never draw into a buffer the compositor may still be reading
+        let index = match surface.swapchain.acquire() {
+            Some(index) => index,
+            None => {
+                println!("All {} buffers are busy, adding another", surface.swapchain.buffers.len());
+                self.surface_add_buffer(wl_surface, surface)?
+            }
+        };
+        let mut drawables = self.drawables.lock().unwrap();
+        let drawables = drawables.entry(wl_surface).or_default();
+        for rect in update(drawables) {
+            add_damage(&mut surface.damage, rect.scaled(surface.scale));
+        }
+        if surface.frame_callback.is_none() && drawables.iter().any(|drawable| drawable.animating()) {
+            let callback = self.new_object(move |client, _, event| match event {
+                wl_callback::Event::Done { .. } => client.wl_surface_frame_done(wl_surface),
+            });
+            self.send(wl_surface, wl_surface::Request::Frame { callback })?;
+            surface.frame_callback = Some(callback);
         }
-        const OPCODE: u16 = 1;
-        const MSG_SIZE: u16 = 20;
-        const X: u32 = 0;
-        const Y: u32 = 0;
 
-        let mut request = vec![0u8; MSG_SIZE as usize];
-        let mut offset: usize = 0;
+        let buffer = &mut surface.swapchain.buffers[index];
+        buffer.busy = true;
+        let full = Rect::new(0, 0, buffer.width, buffer.height);
+        let damage: Vec<Rect> = surface.damage.drain(..).filter_map(|rect| rect.intersect(&full)).collect();
+        // Only what changed since the buffer was last drawn into is drawn again
+        let region = surface.swapchain.repaint(index, &damage).unwrap_or_else(|| vec![full]);
 
-        request.write_u32(&object,   &mut offset);
-        request.write_u16(&OPCODE,   &mut offset);
-        request.write_u16(&MSG_SIZE, &mut offset);
-        request.write_u32(&id,       &mut offset);
-        request.write_u32(&X,	     &mut offset);
-        request.write_u32(&Y,	     &mut offset);
+        let buffer = &surface.swapchain.buffers[index];
+        self.wl_surface_attach(wl_surface, buffer)?;
+        redraw(drawables, &mut ShmCanvas::new(&mut surface.shm_pool, buffer, surface.scale)?, &region, self.config.background);
+        self.wl_surface_damage(wl_surface, &damage, surface.scale)?;
+        self.wl_surface_commit(wl_surface)?;
+        self.flush()?;
 
-        self.socket.lock()?.write(&request)?;
+        Ok(())
+    }
 
+    fn wl_surface_frame_done(&self, wl_surface: u32) -> Result<(), Box<dyn Error>> {
+        // The surface may have been destroyed while the frame was on its way
+        let redraw = match self.surfaces.lock().unwrap().iter_mut().find(|surface| surface.wl_surface == wl_surface) {
+            Some(surface) => {
+                surface.frame_callback = None;
+                surface.redraw
+            }
+            None => return Ok(()),
+        };
+        let animating = self.drawables.lock().unwrap()
+            .get(&wl_surface)
+            .is_some_and(|drawables| drawables.iter().any(|drawable| drawable.animating()));
+        if redraw || animating {
+            self.wl_surface_frame(wl_surface)?;
+        }
         Ok(())
     }
 
-    pub fn wl_surface_commit(&self) -> Result<(), Box<dyn Error>> {
-        let object = self.surface_id.load(Ordering::Relaxed);
-        if object == 0 {
-            return Err(UnsetErr("surface_id".to_string()).into());
+    /// Ask for a frame of a surface, drawn right away unless one is on its way already,
+    /// then when that one is done so redraws never outpace the compositor
+    pub fn surface_redraw(&self, wl_surface: u32) -> Result<(), Box<dyn Error>> {
+        let mut surfaces = self.surfaces.lock().unwrap();
+        let Some(surface) = surfaces.iter_mut().find(|surface| surface.wl_surface == wl_surface) else {
+            return Ok(());
+        };
+        // Unconfigured surfaces draw their first frame on configure
+        if surface.frame_callback.is_some() || surface.swapchain.buffers.is_empty() {
+            surface.redraw = true;
+            return Ok(());
         }
-        const OPCODE: u16 = 6;
-        const MSG_SIZE: u16 = 8;
+        drop(surfaces);
+        self.wl_surface_frame(wl_surface)
+    }
 
-        let mut request = vec![0u8; MSG_SIZE as usize];
-        let mut offset: usize = 0;
+    /// Show drawables in place of the scene of a surface, e.g. after a data change
+    pub fn surface_set_drawables(&self, wl_surface: u32, mut drawables: Vec<Box<dyn Drawable>>) -> Result<(), Box<dyn Error>> {
+        let mut surfaces = self.surfaces.lock().unwrap();
+        let surface = surfaces.iter_mut()
+            .find(|surface| surface.wl_surface == wl_surface)
+            .ok_or(UnsetErr("surface".to_string()))?;
+        // Damage is in buffer pixels, unconfigured surfaces are damaged in full on configure
+        if let Some(buffer) = surface.swapchain.buffers.first() {
+            surface.damage = vec![Rect::new(0, 0, buffer.width, buffer.height)];
+        }
+        let (width, height) = (surface.width, surface.height);
+        drop(surfaces);
 
-        request.write_u32(&object,   &mut offset);
-        request.write_u16(&OPCODE,   &mut offset);
-        request.write_u16(&MSG_SIZE, &mut offset);
+        for drawable in &mut drawables {
+            drawable.layout(width, height);
+        }
+        self.drawables.lock().unwrap().insert(wl_surface, drawables);
+        self.surface_redraw(wl_surface)
+    }
 
-        self.socket.lock().unwrap().write(&request)?;
+    /// Mark logical rectangles of a surface as changed outside of update and ask for a frame
+    pub fn surface_damage(&self, wl_surface: u32, damage: &[Rect]) -> Result<(), Box<dyn Error>> {
+        if let Some(surface) = self.surfaces.lock().unwrap().iter_mut().find(|surface| surface.wl_surface == wl_surface) {
+            for rect in damage {
+                add_damage(&mut surface.damage, rect.scaled(surface.scale));
+            }
+        }
+        self.surface_redraw(wl_surface)
+    }
 
-        Ok(())
+    fn wl_buffer_release(&self, wl_surface: u32, wl_buffer: u32) -> Result<(), Box<dyn Error>> {
+        let mut surfaces = self.surfaces.lock().unwrap();
+        let Some(surface) = surfaces.iter_mut().find(|surface| surface.wl_surface == wl_surface) else {
+            return Ok(());
+        };
+        // Buffers of an older size were only kept alive for the compositor
+        match surface.swapchain.release(wl_buffer) {
+            Some(buffer) => self.send(buffer.id, wl_buffer::Request::Destroy),
+            None => Ok(()),
+        }
+    }
+
+    /// Add a buffer at the current size to the swapchain, growing the pool if it doesn't fit
+    fn surface_add_buffer(&self, wl_surface: u32, surface: &mut Surface) -> Result<usize, Box<dyn Error>> {
+        let (width, height) = (scaled(surface.width, surface.scale), scaled(surface.height, surface.scale));
+        let offset = surface.swapchain.place(width * height);
+        self.surface_grow_pool(surface, (offset + width * height) * 4)?;
+
+        let buffer = WlBuffer {
+            id:     self.new_object(move |client, id, event| match event {
+                wl_buffer::Event::Release => client.wl_buffer_release(wl_surface, id),
+            }),
+            offset, // pixel offset in pool
+            width,
+            height,
+            busy:   false,
+            age:    0,
+            format: self.shm_format(),
+        };
+        self.wl_shm_pool_create_buffer(surface.wl_shm_pool, &buffer)?;
+        surface.swapchain.buffers.push(buffer);
+        Ok(surface.swapchain.buffers.len() - 1)
     }
 
-    pub fn wl_surface_frame(&self) -> Result<(), Box<dyn Error + '_>> {
-        let object = self.surface_id.load(Ordering::Relaxed);
-        if object == 0 {
-            return Err(UnsetErr("surface_id".to_string()).into());
-        }
-        const OPCODE: u16 = 3;
-        const MSG_SIZE: u16 = 12;
-        let id = self.current_id.fetch_add(1, Ordering::Relaxed) + 1;
-        
-        let mut request = vec![0u8; MSG_SIZE as usize];
-        let mut offset: usize = 0;
-
-        request.write_u32(&object,   &mut offset);
-        request.write_u16(&OPCODE,   &mut offset);
-        request.write_u16(&MSG_SIZE, &mut offset);
-        request.write_u32(&id,       &mut offset);
-        self.socket.lock()?.write(&request)?;
-        self.frame_hint_id.store(id, Ordering::Relaxed);
-
-        let buf_lock = if self.active_buffer.fetch_update(
-            Ordering::Relaxed,
-            Ordering::Relaxed,
-            |b| { Some(!b) }
-        ).unwrap() {
-            &self.buffer1
-        } else {
-            &self.buffer2
-        }.lock()?;
-        let buffer = buf_lock.as_ref().ok_or(UnsetErr("buffer".to_string()))?;
-
-        self.wl_surface_attach(buffer)?;
-        let mut drawables = self.drawables.lock()?;
-        let mut shm_pool = self.shm_pool.lock()?;
-        shm_pool.write_raw(0, 0, 800 * 800 * 2);
-        for drawable in &mut *drawables {
-            drawable.update();
-            drawable.draw(buffer, &mut *shm_pool);
-        }
-        self.wl_surface_commit()?;
-        
+    /// Pools can only grow, a smaller size keeps the pool as it is
+    fn surface_grow_pool(&self, surface: &mut Surface, size: usize) -> Result<(), Box<dyn Error>> {
+        if size > surface.shm_pool.size {
+            surface.shm_pool.resize(size)?;
+            self.wl_shm_pool_resize(surface.wl_shm_pool, &surface.shm_pool)?;
+        }
         Ok(())
     }
 
-    pub fn xdg_wm_base_pong(&self, event: &Vec<u8>) -> Result<(), Box<dyn Error>> {
-        let object = self.xdg_wm_base_id.load(Ordering::Relaxed);
-        if object == 0 {
-            return Err(UnsetErr("xdg_wm_base_id".to_string()).into());
+    /// Destroy a launcher surface with its role, buffers and pool
+    pub fn destroy_surface(&self, wl_surface: u32) -> Result<(), Box<dyn Error>> {
+        let mut surfaces = self.surfaces.lock().unwrap();
+        let Some(index) = surfaces.iter().position(|surface| surface.wl_surface == wl_surface) else {
+            return Ok(());
+        };
+        let surface = surfaces.remove(index);
+        drop(surfaces);
+        self.drawables.lock().unwrap().remove(&wl_surface);
+
+        if let Some((fractional_scale, viewport)) = surface.fractional {
+            self.send(fractional_scale, wp_fractional_scale_v1::Request::Destroy)?;
+            self.send(viewport, wp_viewport::Request::Destroy)?;
+        }
+        match surface.role {
+            Role::Layer { layer_surface } => self.send(layer_surface, zwlr_layer_surface_v1::Request::Destroy)?,
+            Role::Toplevel { xdg_surface, xdg_toplevel } => {
+                self.send(xdg_toplevel, xdg_toplevel::Request::Destroy)?;
+                self.send(xdg_surface, xdg_surface::Request::Destroy)?;
+            }
+        }
+        for buffer in surface.swapchain.buffers.iter().chain(&surface.swapchain.retired) {
+            self.send(buffer.id, wl_buffer::Request::Destroy)?;
         }
-        const OPCODE: u16 = 3;
-        const MSG_SIZE: u16 = 12;
-        let serial = u32::from_ne_bytes(event[0..4].try_into().unwrap());
+        self.send(surface.wl_shm_pool, wl_shm_pool::Request::Destroy)?;
+        self.send(wl_surface, wl_surface::Request::Destroy)
+    }
 
-        let mut request = vec![0u8; MSG_SIZE as usize];
-        let mut offset: usize = 0;
+    /// Recreate the buffers of a surface at a new logical size or scale and lay the scene out again
+    pub fn surface_resize(&self, wl_surface: u32, width: usize, height: usize) -> Result<(), Box<dyn Error>> {
+        let mut surfaces = self.surfaces.lock().unwrap();
+        let surface = surfaces.iter_mut()
+            .find(|surface| surface.wl_surface == wl_surface)
+            .ok_or(UnsetErr("surface".to_string()))?;
+        let (pixel_width, pixel_height) = (scaled(width, surface.scale), scaled(height, surface.scale));
+        if surface.width == width && surface.height == height
+            && surface.swapchain.buffers.first().is_some_and(|buffer| buffer.width == pixel_width && buffer.height == pixel_height)
+        {
+            return Ok(());
+        }
+        surface.width = width;
+        surface.height = height;
+        if let Some((_, viewport)) = surface.fractional {
+            self.wp_viewport_set_destination(viewport, width, height)?;
+        }
 
-        request.write_u32(&object,	 &mut offset);
-        request.write_u16(&OPCODE,	 &mut offset);
-        request.write_u16(&MSG_SIZE, &mut offset);
-        request.write_u32(&serial,   &mut offset);
+        // The buffer on screen stays untouched until the compositor releases it
+        for buffer in surface.swapchain.retire() {
+            self.send(buffer.id, wl_buffer::Request::Destroy)?;
+        }
 
-        self.socket.lock().unwrap().write(&request)?;
+        // Grow the pool once for all new buffers rather than for each
+        let len = pixel_width * pixel_height * BUFFER_COUNT;
+        self.surface_grow_pool(surface, (surface.swapchain.place(len) + len) * 4)?;
+        for _ in 0..BUFFER_COUNT {
+            self.surface_add_buffer(wl_surface, surface)?;
+        }
+        // The configure this is part of commits the frame
+        surface.damage = vec![Rect::new(0, 0, pixel_width, pixel_height)];
+        drop(surfaces);
 
+        // Drawables keep their state, only where they go changes
+        for drawable in self.drawables.lock().unwrap().entry(wl_surface).or_default() {
+            drawable.layout(width, height);
+        }
         Ok(())
     }
 }
@@ -0,0 +1,81 @@
+use crate::{graphics::damage::{add_damage, Rect}, wayland::wl_shm::WlBuffer};
+
+/// Frames of damage kept, older buffers are drawn in full
+const MAX_AGE: usize = 4;
+
+/// Buffers of one surface, a buffer is busy from its attach until the compositor releases it
+#[derive(Debug, Default)]
+pub struct Swapchain {
+    /// Buffers at the current size
+    pub buffers:    Vec<WlBuffer>,
+    /// Buffers of an older size the compositor still reads, destroyed once released
+    pub retired:    Vec<WlBuffer>,
+    /// Pixel damage of the last frames, newest first
+    pub history:    Vec<Vec<Rect>>,
+}
+
+impl Swapchain {
+    /// Index of a buffer the compositor doesn't hold, None when all are busy
+    pub fn acquire(&self) -> Option<usize> {
+        self.buffers.iter().position(|buffer| !buffer.busy)
+    }
+
+    /// First pixel offset in the pool where len pixels fit between the buffers in use
+    pub fn place(&self, len: usize) -> usize {
+        let mut taken: Vec<(usize, usize)> = self.buffers.iter()
+            .chain(&self.retired)
+            .map(|buffer| (buffer.offset, buffer.offset + buffer.width * buffer.height))
+            .collect();
+        taken.sort();
+
+        let mut offset = 0;
+        for (start, end) in taken {
+            if start >= offset + len {
+                break;
+            }
+            offset = offset.max(end);
+        }
+        offset
+    }
+
+    /// Record the damage of a frame drawn into the buffer at index, returns what has
+    /// to be drawn again for the buffer to show it, None for everything
+    pub fn repaint(&mut self, index: usize, damage: &[Rect]) -> Option<Vec<Rect>> {
+        self.history.insert(0, damage.to_vec());
+        self.history.truncate(MAX_AGE);
+
+        // A buffer of age n missed the damage of the last n frames, this one included
+        let age = self.buffers[index].age;
+        let region = (age > 0 && age <= self.history.len()).then(|| {
+            let mut region = Vec::new();
+            for rect in self.history[..age].iter().flatten() {
+                add_damage(&mut region, *rect);
+            }
+            region
+        });
+
+        for buffer in self.buffers.iter_mut().filter(|buffer| buffer.age > 0) {
+            buffer.age += 1;
+        }
+        self.buffers[index].age = 1;
+        region
+    }
+
+    /// Take the buffers out of use before a resize, returns the free ones to destroy now
+    pub fn retire(&mut self) -> Vec<WlBuffer> {
+        self.history.clear();
+        let (busy, free): (Vec<WlBuffer>, Vec<WlBuffer>) = self.buffers.drain(..).partition(|buffer| buffer.busy);
+        self.retired.extend(busy);
+        free
+    }
+
+    /// The compositor is done with a buffer, returns it if it was retired and can be destroyed
+    pub fn release(&mut self, id: u32) -> Option<WlBuffer> {
+        if let Some(buffer) = self.buffers.iter_mut().find(|buffer| buffer.id == id) {
+            buffer.busy = false;
+            return None;
+        }
+        let index = self.retired.iter().position(|buffer| buffer.id == id)?;
+        Some(self.retired.remove(index))
+    }
+}
@@ -0,0 +1,779 @@
+use std::{os::fd::AsRawFd, sync::{atomic::Ordering, Arc, Mutex}, thread, time::Duration};
+
+use libc::{fcntl, ftruncate, F_GET_SEALS, F_SEAL_SHRINK};
+
+use crate::{config::Config, graphics::{canvas::Canvas, damage::Rect, drawable::Drawable, format::PixelFormat}, wayland::{mock_compositor::{default_globals, MockCompositor, MockRequest, OUTPUT_HEIGHT, OUTPUT_WIDTH}, pointer::BTN_LEFT, protocol::{wl_buffer, wl_display, wl_keyboard, wl_output, wl_pointer, wl_registry, wl_shm, wl_shm_pool, wl_surface, wl_touch, wp_fractional_scale_manager_v1, wp_fractional_scale_v1, wp_viewporter, xdg_surface, xdg_toplevel, zwlr_layer_shell_v1, zwlr_layer_surface_v1, ErrorCode, Fixed, Interface}, shm::{ShmMap, ShmPool}, wl_client::WlClient, wl_display::ProtocolError}};
+
+const KEY_ESC: u32 = 1;
+const KEY_A: u32 = 30;
+
+/// White 20x20 square moving right by its width every frame
+struct Slider {
+    x: usize,
+}
+
+impl Drawable for Slider {
+    fn update(&mut self) -> bool {
+        self.x += 20;
+        true
+    }
+
+    fn animating(&self) -> bool {
+        true
+    }
+
+    fn bounds(&self) -> Rect {
+        Rect::new(self.x, 0, 20, 20)
+    }
+
+    fn draw(&self, canvas: &mut dyn Canvas) {
+        for row in 0..20 {
+            canvas.blend_row(self.x as isize, row, 20, 0xffffffff);
+        }
+    }
+}
+
+/// Grey 50x50 square at x, 0 that turns white while hovered and logs the input it gets
+struct Target {
+    x:          usize,
+    hovered:    bool,
+    log:        Arc<Mutex<Vec<String>>>,
+}
+
+impl Drawable for Target {
+    fn update(&mut self) -> bool {
+        false
+    }
+
+    fn bounds(&self) -> Rect {
+        Rect::new(self.x, 0, 50, 50)
+    }
+
+    fn hover(&mut self, hovered: bool) -> bool {
+        self.hovered = hovered;
+        self.log.lock().unwrap().push(format!("{} hover {}", self.x, hovered));
+        true
+    }
+
+    fn click(&mut self) -> Option<String> {
+        self.log.lock().unwrap().push(format!("{} click", self.x));
+        None
+    }
+
+    fn scroll(&mut self, dx: f64, dy: f64) -> bool {
+        self.log.lock().unwrap().push(format!("{} scroll {} {}", self.x, dx, dy));
+        false
+    }
+
+    fn draw(&self, canvas: &mut dyn Canvas) {
+        let color = if self.hovered { 0xffffffff } else { 0xff808080 };
+        for row in 0..50 {
+            canvas.blend_row(self.x as isize, row, 50, color);
+        }
+    }
+}
+
+/// Client connected to a mock compositor, started and done with its first frame
+fn start() -> (MockCompositor, Arc<WlClient>) {
+    start_with(default_globals(), Config::default())
+}
+
+fn start_with(globals: Vec<(&'static Interface, u32)>, config: Config) -> (MockCompositor, Arc<WlClient>) {
+    let (mock, socket) = MockCompositor::new(globals);
+    let client = WlClient::new(socket, config).unwrap();
+    client.start().unwrap();
+    // Handles the configure the first commit got back, which draws the first frame
+    settle(&client);
+    (mock, client)
+}
+
+/// Show drawables in place of the demo scene, like a data change would
+fn show(client: &Arc<WlClient>, drawables: Vec<Box<dyn Drawable>>) -> u32 {
+    let wl_surface = client.surfaces.lock().unwrap()[0].wl_surface;
+    client.surface_set_drawables(wl_surface, drawables).unwrap();
+    settle(client);
+    wl_surface
+}
+
+/// Requests sent while handling events go out after the sync of a roundtrip,
+/// the second roundtrip makes sure the mock has seen them too
+fn settle(client: &Arc<WlClient>) {
+    client.roundtrip().unwrap();
+    client.roundtrip().unwrap();
+}
+
+#[test]
+fn binds_every_advertised_global() {
+    let (mock, _client) = start();
+    mock.with_state(|state| {
+        let mut bound: Vec<String> = state.requests_to(&wl_registry::INTERFACE)
+            .filter_map(|recorded| match &recorded.request {
+                MockRequest::Registry(wl_registry::Request::Bind { interface, .. }) => Some(interface.clone()),
+                _ => None,
+            })
+            .collect();
+        bound.sort();
+        let mut advertised: Vec<String> = default_globals().iter().map(|(interface, _)| interface.name.to_string()).collect();
+        advertised.sort();
+        assert_eq!(bound, advertised);
+    });
+}
+
+#[test]
+fn creates_layer_surface_after_globals() {
+    let (mock, _client) = start();
+    mock.with_state(|state| {
+        let last_bind = state.requests.iter()
+            .rposition(|recorded| matches!(recorded.request, MockRequest::Registry(wl_registry::Request::Bind { .. })))
+            .unwrap();
+        let get_layer_surface = state.requests.iter()
+            .position(|recorded| matches!(recorded.request, MockRequest::LayerShell(zwlr_layer_shell_v1::Request::GetLayerSurface { .. })))
+            .unwrap();
+        assert!(last_bind < get_layer_surface);
+
+        assert!(state.requests_to(&zwlr_layer_surface_v1::INTERFACE).any(|recorded| matches!(
+            recorded.request,
+            MockRequest::LayerSurface(zwlr_layer_surface_v1::Request::SetSize { width: 800, height: 800 })
+        )));
+        assert!(state.requests_to(&zwlr_layer_surface_v1::INTERFACE).any(|recorded| matches!(
+            recorded.request,
+            MockRequest::LayerSurface(zwlr_layer_surface_v1::Request::AckConfigure { serial: 1 })
+        )));
+    });
+}
+
+#[test]
+fn first_frame_draws_the_scene() {
+    let (mock, _client) = start();
+    mock.with_state(|state| {
+        let surface = state.surfaces.values().find(|surface| surface.layer_surface.is_some()).unwrap();
+        assert_eq!(surface.size, (800, 800));
+        // Background is cleared, the opaque rectangle is drawn as is
+        assert_eq!(surface.pixel(10, 10), 0);
+        assert_eq!(surface.pixel(100, 100), 0xffff8800);
+    });
+}
+
+#[test]
+fn top_bar_placement_is_requested() {
+    let args = "--layer top --anchor top,left,right --size 0x48 --margin 4 --exclusive-zone 52 --namespace bar";
+    let config = Config::from_args(args.split(' ').map(str::to_string)).unwrap();
+    let (mock, _client) = start_with(default_globals(), config);
+    mock.with_state(|state| {
+        assert!(state.requests_to(&zwlr_layer_shell_v1::INTERFACE).any(|recorded| matches!(
+            &recorded.request,
+            MockRequest::LayerShell(zwlr_layer_shell_v1::Request::GetLayerSurface { layer: 2, namespace, .. }) if namespace == "bar"
+        )));
+        let requests: Vec<&MockRequest> = state.requests_to(&zwlr_layer_surface_v1::INTERFACE)
+            .map(|recorded| &recorded.request)
+            .collect();
+        assert!(requests.iter().any(|request| matches!(
+            request,
+            MockRequest::LayerSurface(zwlr_layer_surface_v1::Request::SetAnchor { anchor: 13 })
+        )));
+        assert!(requests.iter().any(|request| matches!(
+            request,
+            MockRequest::LayerSurface(zwlr_layer_surface_v1::Request::SetMargin { top: 4, right: 4, bottom: 4, left: 4 })
+        )));
+        assert!(requests.iter().any(|request| matches!(
+            request,
+            MockRequest::LayerSurface(zwlr_layer_surface_v1::Request::SetExclusiveZone { zone: 52 })
+        )));
+
+        // The compositor picks the width of a stretched surface
+        let surface = state.surfaces.values().find(|surface| surface.layer_surface.is_some()).unwrap();
+        assert_eq!(surface.size, (OUTPUT_WIDTH as i32, 48));
+    });
+}
+
+#[test]
+fn falls_back_to_xdg_toplevel_without_layer_shell() {
+    let globals = default_globals().into_iter()
+        .filter(|(interface, _)| interface.name != zwlr_layer_shell_v1::NAME)
+        .collect();
+    let (mock, client) = start_with(globals, Config::default());
+    mock.with_state(|state| {
+        let requests: Vec<&MockRequest> = state.requests_to(&xdg_toplevel::INTERFACE)
+            .map(|recorded| &recorded.request)
+            .collect();
+        assert!(requests.iter().any(|request| matches!(
+            request,
+            MockRequest::XdgToplevel(xdg_toplevel::Request::SetTitle { title }) if title == "ChloroStart"
+        )));
+        assert!(requests.iter().any(|request| matches!(
+            request,
+            MockRequest::XdgToplevel(xdg_toplevel::Request::SetAppId { app_id }) if app_id == "chlorostart"
+        )));
+        assert!(requests.iter().any(|request| matches!(
+            request,
+            MockRequest::XdgToplevel(xdg_toplevel::Request::SetMaxSize { width: 800, height: 800 })
+        )));
+        assert!(state.requests_to(&xdg_surface::INTERFACE).any(|recorded| matches!(
+            recorded.request,
+            MockRequest::XdgSurface(xdg_surface::Request::AckConfigure { serial: 1 })
+        )));
+
+        let surface = state.surfaces.values().find(|surface| surface.xdg_toplevel.is_some()).unwrap();
+        assert_eq!(surface.size, (800, 800));
+        assert_eq!(surface.pixel(100, 100), 0xffff8800);
+    });
+
+    let xdg_toplevel = mock.with_state(|state| state.surfaces.values().find_map(|surface| surface.xdg_toplevel).unwrap());
+    mock.with_state(|state| state.send(xdg_toplevel, xdg_toplevel::Event::Close));
+    client.roundtrip().unwrap();
+    assert!(!client.running.load(Ordering::Relaxed));
+    assert!(mock.wait_for(|state| state.requests_to(&xdg_surface::INTERFACE)
+        .any(|recorded| matches!(recorded.request, MockRequest::XdgSurface(xdg_surface::Request::Destroy)))));
+}
+
+/// Default globals with a second monitor right of the first
+fn two_outputs() -> Vec<(&'static Interface, u32)> {
+    let mut globals = default_globals();
+    globals.push((&wl_output::INTERFACE, 4));
+    globals
+}
+
+/// wl_output ids the layer surfaces were created on
+fn layer_surface_outputs(mock: &MockCompositor) -> Vec<u32> {
+    mock.with_state(|state| state.requests_to(&zwlr_layer_shell_v1::INTERFACE)
+        .filter_map(|recorded| match recorded.request {
+            MockRequest::LayerShell(zwlr_layer_shell_v1::Request::GetLayerSurface { output, .. }) => Some(output),
+            _ => None,
+        })
+        .collect())
+}
+
+#[test]
+fn tracks_outputs_and_opens_on_named_output() {
+    let config = Config::from_args(["--output", "MOCK-2"].into_iter().map(str::to_string)).unwrap();
+    let (mock, client) = start_with(two_outputs(), config);
+
+    let outputs = client.outputs.lock().unwrap().clone();
+    assert_eq!(outputs.len(), 2);
+    assert!(outputs.iter().all(|output| output.ready && output.xdg_output.is_some()));
+    let second = outputs.iter().find(|output| output.name.as_deref() == Some("MOCK-2")).unwrap();
+    assert_eq!((second.x, second.y, second.width, second.height), (OUTPUT_WIDTH as i32, 0, OUTPUT_WIDTH as i32, OUTPUT_HEIGHT as i32));
+    assert_eq!(second.description.as_deref(), Some("Mock monitor 1"));
+
+    assert_eq!(layer_surface_outputs(&mock), vec![second.wl_output]);
+}
+
+#[test]
+fn all_outputs_follow_hotplug() {
+    let config = Config::from_args(["--output", "all"].into_iter().map(str::to_string)).unwrap();
+    let (mock, client) = start_with(two_outputs(), config);
+    assert_eq!(layer_surface_outputs(&mock).len(), 2);
+
+    let name = mock.with_state(|state| state.add_global(&wl_output::INTERFACE, 4));
+    // The bind, the output describing itself and the new surface each need a roundtrip
+    settle(&client);
+    settle(&client);
+    assert_eq!(layer_surface_outputs(&mock).len(), 3);
+    assert_eq!(client.surfaces.lock().unwrap().len(), 3);
+
+    let unplugged = mock.with_state(|state| *state.outputs.iter().find(|(_, global)| **global == name).unwrap().0);
+    mock.with_state(|state| state.remove_global(name));
+    settle(&client);
+    let surfaces = client.surfaces.lock().unwrap();
+    assert_eq!(surfaces.len(), 2);
+    assert!(surfaces.iter().all(|surface| surface.output != Some(unplugged)));
+    drop(surfaces);
+    assert!(mock.with_state(|state| state.requests.iter().any(|recorded| recorded.object == unplugged
+        && matches!(recorded.request, MockRequest::Output(wl_output::Request::Release)))));
+}
+
+#[test]
+fn named_output_plugged_in_later_gets_the_launcher() {
+    let config = Config::from_args(["--output", "MOCK-2"].into_iter().map(str::to_string)).unwrap();
+    let (mock, client) = start_with(default_globals(), config);
+    // Not there yet, the compositor picks
+    assert_eq!(layer_surface_outputs(&mock), vec![0]);
+    let fallback = client.surfaces.lock().unwrap()[0].wl_surface;
+
+    let name = mock.with_state(|state| state.add_global(&wl_output::INTERFACE, 4));
+    settle(&client);
+    settle(&client);
+    let plugged = mock.with_state(|state| *state.outputs.iter().find(|(_, global)| **global == name).unwrap().0);
+    assert_eq!(layer_surface_outputs(&mock), vec![0, plugged]);
+    let surfaces = client.surfaces.lock().unwrap();
+    assert_eq!(surfaces.len(), 1);
+    assert_eq!(surfaces[0].output, Some(plugged));
+    drop(surfaces);
+    assert!(mock.wait_for(|state| state.requests.iter().any(|recorded| recorded.object == fallback
+        && matches!(recorded.request, MockRequest::Surface(wl_surface::Request::Destroy)))));
+}
+
+#[test]
+fn configure_resizes_buffers_and_scene() {
+    let (mock, client) = start();
+    mock.configure(1200, 900);
+    settle(&client);
+    // The old buffer is destroyed once the compositor releases it for the new one
+    assert!(mock.wait_for(|state| state.requests_to(&wl_buffer::INTERFACE).count() == 2));
+    mock.with_state(|state| {
+        // 1200x900 double buffered goes after the 800x800 buffer still on screen
+        assert!(state.requests_to(&wl_shm_pool::INTERFACE).any(|recorded| matches!(
+            recorded.request,
+            MockRequest::ShmPool(wl_shm_pool::Request::Resize { size: 11_200_000 })
+        )));
+        assert_eq!(state.buffers.len(), 2);
+
+        let surface = state.surfaces.values().find(|surface| surface.layer_surface.is_some()).unwrap();
+        assert_eq!(surface.size, (1200, 900));
+        // The scene stays centered
+        assert_eq!(surface.pixel(100, 100), 0);
+        assert_eq!(surface.pixel(300, 150), 0xffff8800);
+    });
+}
+
+#[test]
+fn configure_lays_out_the_scene_without_replacing_it() {
+    let (mock, client) = start();
+    let wl_surface = client.surfaces.lock().unwrap()[0].wl_surface;
+    let (surface_x, surface_y) = (Fixed::from_f64(100.0), Fixed::from_f64(100.0));
+    mock.pointer(|serial| vec![wl_pointer::Event::Enter { serial, surface: wl_surface, surface_x, surface_y }]);
+    settle(&client);
+    let hovered = mock.with_state(|state| state.surfaces[&wl_surface].pixel(100, 100));
+    assert_ne!(hovered, 0xffff8800);
+
+    mock.configure(1200, 900);
+    settle(&client);
+    // Moved with the centered scene and still hovered, nothing tells it the pointer left
+    mock.with_state(|state| {
+        assert_eq!(state.surfaces[&wl_surface].pixel(100, 100), 0);
+        assert_eq!(state.surfaces[&wl_surface].pixel(300, 150), hovered);
+    });
+}
+
+#[test]
+fn opaque_background_drops_alpha_from_buffers() {
+    let formats = |args: &[&str]| {
+        let config = Config::from_args(args.iter().map(|arg| arg.to_string())).unwrap();
+        let (mock, _client) = start_with(default_globals(), config);
+        mock.with_state(|state| {
+            let surface = state.surfaces.values().next().unwrap();
+            let format = state.buffers[&surface.buffer.unwrap()].format;
+            (format, surface.pixel(10, 10), surface.pixel(100, 100))
+        })
+    };
+
+    assert_eq!(formats(&[]), (wl_shm::Format::Argb8888 as u32, 0, 0xffff8800));
+    assert_eq!(formats(&["--background", "80202020"]).0, wl_shm::Format::Argb8888 as u32);
+    assert_eq!(formats(&["--background", "#202020"]), (wl_shm::Format::Xrgb8888 as u32, 0xff202020, 0xffff8800));
+    let deep = PixelFormat::Xrgb2101010;
+    assert_eq!(
+        formats(&["--background", "202020", "--deep-color"]),
+        (wl_shm::Format::Xrgb2101010 as u32, deep.encode(0xff202020), deep.encode(0xffff8800)),
+    );
+}
+
+#[test]
+fn preferred_scale_renders_at_double_size() {
+    let (mock, client) = start();
+    mock.with_state(|state| {
+        let surface = *state.surfaces.keys().next().unwrap();
+        state.send(surface, wl_surface::Event::PreferredBufferScale { factor: 2 });
+    });
+    settle(&client);
+    mock.with_state(|state| {
+        let surface = state.surfaces.values().next().unwrap();
+        assert_eq!(surface.buffer_scale, 2);
+        assert_eq!(surface.size, (1600, 1600));
+        // The rectangle at 50,50 logical starts at 100,100 in pixels
+        assert_eq!(surface.pixel(90, 400), 0);
+        assert_eq!(surface.pixel(110, 400), 0xffff8800);
+    });
+
+    // A new scene damages the whole buffer, not just its logical size
+    let wl_surface = show(&client, vec![Box::new(Slider { x: 0 })]);
+    mock.with_state(|state| assert_eq!(state.surfaces[&wl_surface].damage, [(0, 0, 1600, 1600)]));
+}
+
+#[test]
+fn fractional_scale_renders_through_viewport() {
+    let mut globals = default_globals();
+    globals.push((&wp_fractional_scale_manager_v1::INTERFACE, 1));
+    globals.push((&wp_viewporter::INTERFACE, 1));
+    let (mock, client) = start_with(globals, Config::default());
+    mock.with_state(|state| {
+        let fractional_scale = state.surfaces.values().next().unwrap().fractional_scale.unwrap();
+        // 1.5 in 120ths, preferred_buffer_scale is ignored next to it
+        state.send(fractional_scale, wp_fractional_scale_v1::Event::PreferredScale { scale: 180 });
+        let surface = *state.surfaces.keys().next().unwrap();
+        state.send(surface, wl_surface::Event::PreferredBufferScale { factor: 2 });
+    });
+    settle(&client);
+    mock.with_state(|state| {
+        let surface = state.surfaces.values().next().unwrap();
+        assert_eq!(surface.buffer_scale, 0);
+        assert_eq!(surface.size, (1200, 1200));
+        assert_eq!(surface.destination, (800, 800));
+        assert_eq!(surface.pixel(70, 300), 0);
+        assert_eq!(surface.pixel(80, 300), 0xffff8800);
+    });
+}
+
+#[test]
+fn frame_callback_ids_are_recycled() {
+    let (mock, client) = start();
+    show(&client, vec![Box::new(Slider { x: 0 })]);
+    let frames = |mock: &MockCompositor| mock.with_state(|state| {
+        state.requests_to(&wl_surface::INTERFACE)
+            .filter_map(|recorded| match recorded.request {
+                MockRequest::Surface(wl_surface::Request::Frame { callback }) => Some(callback),
+                _ => None,
+            })
+            .collect::<Vec<u32>>()
+    });
+
+    for frame in 0..3 {
+        mock.frame_done();
+        // No roundtrips, their sync callbacks would take freed ids as well
+        for _ in 0..500 {
+            if frames(&mock).len() == frame + 2 {
+                break;
+            }
+            client.dispatch_sources(Some(Duration::from_millis(10))).unwrap();
+        }
+    }
+    let callbacks = frames(&mock);
+    assert_eq!(callbacks.len(), 4);
+    // The callback of the next frame is created before delete_id frees the current one
+    assert!(callbacks[2..].iter().all(|callback| callbacks[..2].contains(callback)), "{:?}", callbacks);
+}
+
+#[test]
+fn static_scene_is_drawn_only_on_demand() {
+    let (mock, client) = start();
+    let counts = || mock.with_state(|state| (
+        state.surfaces.values().next().unwrap().commits,
+        state.requests_to(&wl_surface::INTERFACE)
+            .filter(|recorded| matches!(recorded.request, MockRequest::Surface(wl_surface::Request::Frame { .. })))
+            .count(),
+    ));
+    let (commits, frames) = counts();
+    assert_eq!(frames, 0);
+    mock.frame_done();
+    settle(&client);
+    assert_eq!(counts(), (commits, 0));
+
+    // Keys damage nothing, so nothing is drawn
+    mock.key(KEY_A, wl_keyboard::KeyState::Pressed);
+    mock.key(KEY_A, wl_keyboard::KeyState::Released);
+    settle(&client);
+    assert_eq!(counts(), (commits, 0));
+
+    // Hovering a rectangle damages it, one frame draws that without starting a frame loop
+    let wl_surface = client.surfaces.lock().unwrap()[0].wl_surface;
+    let (surface_x, surface_y) = (Fixed::from_f64(100.0), Fixed::from_f64(100.0));
+    mock.pointer(|serial| vec![wl_pointer::Event::Enter { serial, surface: wl_surface, surface_x, surface_y }]);
+    settle(&client);
+    assert_eq!(counts(), (commits + 1, 0));
+}
+
+#[test]
+fn busy_buffers_are_never_drawn_into() {
+    let (mock, client) = start();
+    let buffers = |mock: &MockCompositor| mock.with_state(|state| state.buffers.len());
+    show(&client, vec![Box::new(Slider { x: 0 })]);
+    mock.with_state(|state| state.hold_buffers = true);
+    // Both buffers end up attached and held, the mock fails on any attach of a held buffer
+    for _ in 0..2 {
+        mock.frame_done();
+        settle(&client);
+    }
+    assert_eq!(buffers(&mock), 3);
+
+    mock.release_held();
+    for _ in 0..3 {
+        mock.frame_done();
+        settle(&client);
+    }
+    assert_eq!(buffers(&mock), 3);
+    assert!(client.surfaces.lock().unwrap()[0].swapchain.buffers.iter().filter(|buffer| buffer.busy).count() <= 1);
+}
+
+#[test]
+fn only_damage_is_redrawn_and_sent() {
+    let (mock, client) = start();
+    let wl_surface = show(&client, vec![Box::new(Slider { x: 0 })]);
+    let frame = || {
+        mock.frame_done();
+        settle(&client);
+        mock.with_state(|state| {
+            let surface = &state.surfaces[&wl_surface];
+            (surface.damage.clone(), surface.pixel(25, 10), surface.pixel(45, 10), surface.pixel(65, 10))
+        })
+    };
+
+    // New drawables damage everything
+    mock.with_state(|state| assert_eq!(state.surfaces[&wl_surface].damage, [(0, 0, 800, 800)]));
+    // The old and the new place of the square, a pixel wider for rounding
+    assert_eq!(frame(), (vec![(19, 0, 42, 21)], 0, 0xffffffff, 0));
+    // This buffer last showed the square at 20, two frames of damage clear it
+    assert_eq!(frame(), (vec![(39, 0, 42, 21)], 0, 0, 0xffffffff));
+    mock.with_state(|state| assert_eq!(state.surfaces[&wl_surface].pixel(100, 100), 0));
+}
+
+#[test]
+fn pools_grow_but_never_shrink() {
+    let mut pool = ShmPool::new(4096).unwrap();
+    let fd = pool.fd.as_raw_fd();
+    assert_ne!(unsafe { fcntl(fd, F_GET_SEALS) } & F_SEAL_SHRINK, 0);
+    assert_eq!(unsafe { ftruncate(fd, 0) }, -1);
+
+    pool.pixels_mut()[0] = 0xff00ff00;
+    pool.resize(8192).unwrap();
+    assert_eq!(pool.pixels_mut().len(), 2048);
+    assert_eq!(pool.pixels_mut()[0], 0xff00ff00);
+
+    // Mappings of fds from the compositor are read only and only hand out bytes
+    let map = ShmMap::new(pool.fd.try_clone().unwrap(), 4).unwrap();
+    assert_eq!(map.bytes(), 0xff00ff00u32.to_ne_bytes());
+}
+
+#[test]
+fn escape_exits_and_destroys_everything() {
+    let (mock, client) = start();
+    mock.key(KEY_ESC, wl_keyboard::KeyState::Pressed);
+    mock.key(KEY_ESC, wl_keyboard::KeyState::Released);
+    client.roundtrip().unwrap();
+    assert!(!client.running.load(Ordering::Relaxed));
+
+    assert!(mock.wait_for(|state| {
+        state.requests_to(&zwlr_layer_surface_v1::INTERFACE)
+            .any(|recorded| matches!(recorded.request, MockRequest::LayerSurface(zwlr_layer_surface_v1::Request::Destroy)))
+            && state.requests_to(&wl_shm_pool::INTERFACE)
+                .any(|recorded| matches!(recorded.request, MockRequest::ShmPool(wl_shm_pool::Request::Destroy)))
+            && state.requests_to(&wl_buffer::INTERFACE).count() == 2
+    }));
+}
+
+#[test]
+fn on_demand_keyboard_waits_for_focus() {
+    let config = Config::from_args(["--keyboard", "on-demand"].into_iter().map(str::to_string)).unwrap();
+    let (mock, client) = start_with(default_globals(), config);
+    assert!(mock.with_state(|state| state.requests_to(&zwlr_layer_surface_v1::INTERFACE).any(|recorded| matches!(
+        recorded.request,
+        MockRequest::LayerSurface(zwlr_layer_surface_v1::Request::SetKeyboardInteractivity { keyboard_interactivity: 2 })
+    ))));
+    assert_eq!(*client.keyboard_focus.lock().unwrap(), None);
+
+    // Unlike exclusive the keyboard stays with whoever had it until the user clicks
+    mock.key(KEY_ESC, wl_keyboard::KeyState::Released);
+    client.roundtrip().unwrap();
+    assert!(client.running.load(Ordering::Relaxed));
+
+    let surface = mock.with_state(|state| {
+        let surface = state.surfaces.iter().find(|(_, surface)| surface.layer_surface.is_some()).map(|(id, _)| *id);
+        state.focus(surface);
+        surface
+    });
+    mock.key(KEY_ESC, wl_keyboard::KeyState::Released);
+    client.roundtrip().unwrap();
+    assert_eq!(*client.keyboard_focus.lock().unwrap(), surface);
+    assert!(!client.running.load(Ordering::Relaxed));
+}
+
+#[test]
+fn on_demand_keyboard_falls_back_to_none_before_v4() {
+    let globals = default_globals().into_iter()
+        .map(|(interface, version)| match interface.name == zwlr_layer_shell_v1::NAME {
+            true => (interface, 3),
+            false => (interface, version),
+        })
+        .collect();
+    let config = Config::from_args(["--keyboard", "on-demand"].into_iter().map(str::to_string)).unwrap();
+    let (mock, _client) = start_with(globals, config);
+    let modes: Vec<u32> = mock.with_state(|state| state.requests_to(&zwlr_layer_surface_v1::INTERFACE)
+        .filter_map(|recorded| match recorded.request {
+            MockRequest::LayerSurface(zwlr_layer_surface_v1::Request::SetKeyboardInteractivity { keyboard_interactivity }) => {
+                Some(keyboard_interactivity)
+            }
+            _ => None,
+        })
+        .collect());
+    assert_eq!(modes, [zwlr_layer_surface_v1::KeyboardInteractivity::None as u32]);
+}
+
+#[test]
+fn pointer_hovers_clicks_and_scrolls_what_it_is_over() {
+    let (mock, client) = start();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let wl_surface = show(&client, vec![
+        Box::new(Target { x: 0, hovered: false, log: log.clone() }),
+        Box::new(Target { x: 100, hovered: false, log: log.clone() }),
+    ]);
+    let point = |x: f64| (Fixed::from_f64(x), Fixed::from_f64(10.0));
+    let colors = || {
+        settle(&client);
+        mock.with_state(|state| (state.surfaces[&wl_surface].pixel(10, 10), state.surfaces[&wl_surface].pixel(110, 10)))
+    };
+    let button = |serial, state: wl_pointer::ButtonState| {
+        wl_pointer::Event::Button { serial, time: 0, button: BTN_LEFT, state: state as u32 }
+    };
+
+    mock.pointer(|serial| {
+        let (surface_x, surface_y) = point(10.0);
+        vec![wl_pointer::Event::Enter { serial, surface: wl_surface, surface_x, surface_y }]
+    });
+    assert_eq!(colors(), (0xffffffff, 0xff808080));
+    let (surface_x, surface_y) = point(110.0);
+    mock.pointer(|_| vec![wl_pointer::Event::Motion { time: 0, surface_x, surface_y }]);
+    assert_eq!(colors(), (0xff808080, 0xffffffff));
+
+    // A press and release in one frame is a click, one that ends elsewhere isn't
+    mock.pointer(|serial| vec![button(serial, wl_pointer::ButtonState::Pressed), button(serial, wl_pointer::ButtonState::Released)]);
+    mock.pointer(|serial| vec![button(serial, wl_pointer::ButtonState::Pressed)]);
+    let (surface_x, surface_y) = point(60.0);
+    mock.pointer(|_| vec![wl_pointer::Event::Motion { time: 0, surface_x, surface_y }]);
+    mock.pointer(|serial| vec![button(serial, wl_pointer::ButtonState::Released)]);
+
+    // Wheel steps win over the plain axis value of the same frame, touchpads only send that
+    let (surface_x, surface_y) = point(10.0);
+    mock.pointer(|_| vec![
+        wl_pointer::Event::Motion { time: 0, surface_x, surface_y },
+        wl_pointer::Event::Axis { time: 0, axis: wl_pointer::Axis::VerticalScroll as u32, value: Fixed::from_f64(15.0) },
+        wl_pointer::Event::AxisValue120 { axis: wl_pointer::Axis::VerticalScroll as u32, value120: 60 },
+    ]);
+    mock.pointer(|_| vec![
+        wl_pointer::Event::Axis { time: 0, axis: wl_pointer::Axis::HorizontalScroll as u32, value: Fixed::from_f64(-7.5) },
+    ]);
+    mock.pointer(|serial| vec![wl_pointer::Event::Leave { serial, surface: wl_surface }]);
+    assert_eq!(colors(), (0xff808080, 0xff808080));
+
+    assert_eq!(*log.lock().unwrap(), [
+        "0 hover true",
+        "0 hover false",
+        "100 hover true",
+        "100 click",
+        "100 hover false",
+        "0 hover true",
+        "0 scroll 0 20",
+        "0 scroll -7.5 0",
+        "0 hover false",
+    ]);
+}
+
+#[test]
+fn touch_taps_drags_flings_and_swipes_away() {
+    let (mock, client) = start();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let wl_surface = show(&client, vec![Box::new(Target { x: 0, hovered: false, log: log.clone() })]);
+    let down = |serial, time, x: f64, y: f64| {
+        wl_touch::Event::Down { serial, time, surface: wl_surface, id: 0, x: Fixed::from_f64(x), y: Fixed::from_f64(y) }
+    };
+    let motion = |time, x: f64, y: f64| wl_touch::Event::Motion { time, id: 0, x: Fixed::from_f64(x), y: Fixed::from_f64(y) };
+    let up = |serial, time| wl_touch::Event::Up { serial, time, id: 0 };
+    let scrolls = || -> Vec<f64> {
+        log.lock().unwrap().iter().filter_map(|entry| entry.strip_prefix("0 scroll 0 ")?.parse().ok()).collect()
+    };
+
+    // A touch that barely moves is a tap
+    mock.touch(|serial| vec![down(serial, 0, 10.0, 40.0)]);
+    mock.touch(|_| vec![motion(5, 12.0, 35.0)]);
+    mock.touch(|serial| vec![up(serial, 10)]);
+    settle(&client);
+    assert_eq!(*log.lock().unwrap(), ["0 click"]);
+
+    // Dragging up scrolls down by as much as the finger moved
+    mock.touch(|serial| vec![down(serial, 100, 10.0, 40.0)]);
+    mock.touch(|_| vec![motion(110, 10.0, 20.0)]);
+    mock.touch(|_| vec![motion(120, 10.0, 0.0)]);
+    settle(&client);
+    assert_eq!(scrolls(), [20.0, 20.0]);
+
+    // Letting go keeps it scrolling slower and slower, a touch stops it
+    mock.touch(|serial| vec![up(serial, 120)]);
+    for _ in 0..500 {
+        if scrolls().len() >= 5 {
+            break;
+        }
+        client.roundtrip().unwrap();
+        thread::sleep(Duration::from_millis(5));
+    }
+    let fling = scrolls().split_off(2);
+    assert!(fling.len() >= 3 && fling[0] == 24.0);
+    assert!(fling.windows(2).all(|steps| steps[1] < steps[0]));
+    mock.touch(|serial| vec![down(serial, 200, 10.0, 40.0)]);
+    mock.touch(|_| vec![wl_touch::Event::Cancel]);
+    settle(&client);
+    let stopped = scrolls().len();
+    thread::sleep(Duration::from_millis(50));
+    settle(&client);
+    assert_eq!(scrolls().len(), stopped);
+
+    // Only a long enough sideways swipe dismisses the launcher
+    for (time, distance) in [(300, 100.0), (400, 200.0)] {
+        mock.touch(|serial| vec![down(serial, time, 100.0, 300.0)]);
+        mock.touch(|_| vec![motion(time + 50, 100.0 + distance, 300.0)]);
+        mock.touch(|serial| vec![up(serial, time + 50)]);
+        settle(&client);
+        assert_eq!(client.running.load(Ordering::Relaxed), distance < 150.0);
+    }
+}
+
+#[test]
+fn launched_apps_are_detached_but_piped_to_the_launcher() {
+    let (_mock, client) = start();
+    let path = std::env::temp_dir().join(format!("chlorostart-launch-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    // A session leader's session id is its own pid, SIGPIPE is bit 12 of the ignored signals
+    client.launch(&format!(
+        "[ $(cut -d' ' -f6 /proc/$$/stat) = $$ ] \
+            && echo \"$(readlink /proc/$$/fd/1 /proc/$$/fd/2 | cut -d: -f1) $(( 0x$(grep SigIgn /proc/$$/status | cut -f2) >> 12 & 1 ))\" > {}.part \
+            && mv {0}.part {0}",
+        path.display(),
+    )).unwrap();
+    for _ in 0..500 {
+        if path.exists() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "pipe\npipe 1\n");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn protocol_error_is_returned_from_dispatch() {
+    let (mock, client) = start();
+    let surface = mock.with_state(|state| {
+        let surface = *state.surfaces.keys().next().unwrap();
+        state.send(1, wl_display::Event::Error {
+            object_id:  surface,
+            code:       wl_surface::Error::InvalidSize as u32,
+            message:    "mock error".to_string(),
+        });
+        surface
+    });
+
+    let err = client.roundtrip().unwrap_err();
+    let err = err.downcast_ref::<ProtocolError>().unwrap();
+    assert_eq!(err.interface, wl_surface::NAME);
+    assert_eq!(err.object, surface);
+    assert_eq!(err.code, ErrorCode::WlSurface(wl_surface::Error::InvalidSize));
+    assert_eq!(err.message, "mock error");
+}
+
+#[test]
+fn events_for_destroyed_objects_are_dropped() {
+    let (mock, client) = start();
+    let keyboard = mock.with_state(|state| state.keyboards[0]);
+    let keymap = client.keymap.read().unwrap().clone();
+    client.send(keyboard, wl_keyboard::Request::Release).unwrap();
+    assert!(client.objects.lock().unwrap().get(keyboard).is_some_and(|object| object.zombie));
+
+    // Sent before the compositor has seen the release
+    mock.with_state(|state| {
+        let fd = std::fs::File::open("/dev/null").unwrap().into();
+        state.send(keyboard, wl_keyboard::Event::Keymap { format: wl_keyboard::KeymapFormat::XkbV1 as u32, fd, size: 0 });
+        state.send(keyboard, wl_keyboard::Event::Key { serial: 1, time: 0, key: KEY_ESC, state: wl_keyboard::KeyState::Released as u32 });
+    });
+    settle(&client);
+
+    assert!(client.running.load(Ordering::Relaxed));
+    assert_eq!(*client.keymap.read().unwrap(), keymap);
+    // delete_id freed the id, the syncs of settle may have taken it since
+    assert!(client.objects.lock().unwrap().get(keyboard).is_none_or(|object| object.interface.name != wl_keyboard::NAME));
+}
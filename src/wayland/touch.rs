@@ -0,0 +1,200 @@
+use std::{error::Error, time::Duration};
+
+use crate::wayland::{protocol::{wl_seat, wl_touch}, wl_client::WlClient};
+
+/// Logical distance a touch may move and still be a tap
+const TAP_SLOP: f64 = 10.0;
+/// Logical distance a sideways swipe has to cover to dismiss the launcher
+const SWIPE_DISTANCE: f64 = 150.0;
+/// A finger resting longer than this before it lifts doesn't fling
+const FLING_PAUSE: u32 = 50; // ms
+/// Time between the steps of a fling
+const FLING_STEP: Duration = Duration::from_millis(16);
+/// Share of a fling's velocity kept every step
+const FLING_FRICTION: f64 = 0.95;
+/// Flings slower than this stop, logical units per ms
+const FLING_MIN_VELOCITY: f64 = 0.05;
+
+/// What a touch point turned out to be, every touch starts as a tap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    Tap,
+    /// Dragged up or down, scrolls the drawable it started on
+    Scroll,
+    /// Dragged sideways, dismisses the launcher if it goes far enough
+    Swipe,
+}
+
+#[derive(Debug)]
+pub struct TouchPoint {
+    pub id:         i32,
+    /// Logical positions where the touch went down, where it is and where the last frame saw it
+    pub start:      (f64, f64),
+    pub position:   (f64, f64),
+    pub handled:    (f64, f64),
+    /// Time of the last event in ms
+    pub time:       u32,
+    /// Scroll speed in logical units per ms
+    pub velocity:   f64,
+    /// Drawable the touch went down on as surface and index
+    pub target:     Option<(u32, usize)>,
+    pub gesture:    Gesture,
+    pub up:         bool,
+}
+
+/// Touch points are updated by their events and handled together on wl_touch.frame
+#[derive(Debug, Default)]
+pub struct Touch {
+    pub points: Vec<TouchPoint>,
+    pub timer:  Option<u64>,
+    /// Drawable a released scroll keeps moving and its velocity
+    pub fling:  Option<((u32, usize), f64)>,
+}
+
+impl WlClient {
+    pub fn wl_seat_get_touch(&self, seat: u32) -> Result<u32, Box<dyn Error>> {
+        let id = self.new_object(|client, _, event| client.wl_touch_event(event));
+        self.send(seat, wl_seat::Request::GetTouch { id })?;
+
+        let mut touch = self.touch.lock().unwrap();
+        if touch.timer.is_none() {
+            touch.timer = Some(self.event_loop.lock().unwrap().add_timer(|client| client.wl_touch_fling())?);
+        }
+        Ok(id)
+    }
+
+    pub fn wl_touch_event(&self, event: wl_touch::Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            wl_touch::Event::Down { time, surface, id, x, y, .. } => {
+                // Touching stops a fling, like holding a spinning wheel
+                self.wl_touch_set_fling(None)?;
+                let position = (x.to_f64(), y.to_f64());
+                let target = self.drawable_at(surface, position.0, position.1);
+                self.touch.lock().unwrap().points.push(TouchPoint {
+                    id,
+                    start:      position,
+                    position,
+                    handled:    position,
+                    time,
+                    velocity:   0.0,
+                    target,
+                    gesture:    Gesture::Tap,
+                    up:         false,
+                });
+            }
+            wl_touch::Event::Motion { time, id, x, y } => {
+                let mut touch = self.touch.lock().unwrap();
+                if let Some(point) = touch.points.iter_mut().find(|point| point.id == id) {
+                    let position = (x.to_f64(), y.to_f64());
+                    // Content follows the finger, so moving up scrolls down
+                    if time > point.time {
+                        let velocity = (point.position.1 - position.1) / (time - point.time) as f64;
+                        point.velocity = (point.velocity + velocity) / 2.0;
+                    }
+                    point.position = position;
+                    point.time = time;
+                }
+            }
+            wl_touch::Event::Up { time, id, .. } => {
+                let mut touch = self.touch.lock().unwrap();
+                if let Some(point) = touch.points.iter_mut().find(|point| point.id == id) {
+                    if time.saturating_sub(point.time) > FLING_PAUSE {
+                        point.velocity = 0.0;
+                    }
+                    point.up = true;
+                }
+            }
+            wl_touch::Event::Frame => self.wl_touch_frame()?,
+            // The compositor took the touches for a gesture of its own
+            wl_touch::Event::Cancel => {
+                println!("Touch sequence cancelled");
+                self.touch.lock().unwrap().points.clear();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Handle what the touch points did in a frame: scroll what is dragged, click what is
+    /// tapped and fling or dismiss once a drag is let go
+    fn wl_touch_frame(&self) -> Result<(), Box<dyn Error>> {
+        let mut scrolls = Vec::new();
+        let mut taps = Vec::new();
+        let mut fling = None;
+        let mut dismiss = false;
+        {
+            let mut touch = self.touch.lock().unwrap();
+            for point in touch.points.iter_mut() {
+                let (dx, dy) = (point.position.0 - point.start.0, point.position.1 - point.start.1);
+                if point.gesture == Gesture::Tap && dx.hypot(dy) > TAP_SLOP {
+                    point.gesture = if dy.abs() >= dx.abs() { Gesture::Scroll } else { Gesture::Swipe };
+                }
+                if let Some(target) = point.target && point.gesture == Gesture::Scroll {
+                    scrolls.push((target, point.handled.1 - point.position.1));
+                }
+                point.handled = point.position;
+                if !point.up {
+                    continue;
+                }
+                match point.gesture {
+                    Gesture::Tap => taps.extend(point.target),
+                    Gesture::Scroll if point.velocity.abs() >= FLING_MIN_VELOCITY => {
+                        fling = point.target.map(|target| (target, point.velocity));
+                    }
+                    Gesture::Scroll => {}
+                    Gesture::Swipe => dismiss |= dx.abs() >= SWIPE_DISTANCE,
+                }
+            }
+            touch.points.retain(|point| !point.up);
+        }
+
+        for (target, dy) in scrolls.into_iter().filter(|(_, dy)| *dy != 0.0) {
+            self.drawable_input(target, |drawable| drawable.scroll(0.0, dy))?;
+        }
+        if fling.is_some() {
+            self.wl_touch_set_fling(fling)?;
+        }
+        for target in taps {
+            let mut command = None;
+            self.drawable_input(target, |drawable| {
+                command = drawable.click();
+                false
+            })?;
+            if let Some(command) = command {
+                self.launch(&command)?;
+            }
+        }
+        if dismiss {
+            println!("Swiped away");
+            self.exit()?;
+        }
+        Ok(())
+    }
+
+    /// Start scrolling a drawable on its own after a drag, None stops it
+    fn wl_touch_set_fling(&self, fling: Option<((u32, usize), f64)>) -> Result<(), Box<dyn Error>> {
+        let mut touch = self.touch.lock().unwrap();
+        let Some(timer) = touch.timer else {
+            return Ok(());
+        };
+        // The timer only changes when a fling starts or stops
+        let flinging = touch.fling.is_some();
+        touch.fling = fling;
+        if flinging != fling.is_some() {
+            let step = fling.map(|_| FLING_STEP);
+            self.event_loop.lock().unwrap().set_timer(timer, step, step)?;
+        }
+        Ok(())
+    }
+
+    /// One step of a fling, slowing down until it stops
+    fn wl_touch_fling(&self) -> Result<(), Box<dyn Error>> {
+        let Some((target, velocity)) = self.touch.lock().unwrap().fling else {
+            return Ok(());
+        };
+        let next = velocity * FLING_FRICTION;
+        self.wl_touch_set_fling((next.abs() >= FLING_MIN_VELOCITY).then_some((target, next)))?;
+        let dy = velocity * FLING_STEP.as_millis() as f64;
+        self.drawable_input(target, |drawable| drawable.scroll(0.0, dy))
+    }
+}
@@ -0,0 +1,91 @@
+use std::{env::var, os::fd::RawFd, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::wayland::{object_map::ObjectMap, protocol::{ArgKind, DecodeErr, MessageDesc, WlMessage}, wl_client::WlClient};
+
+/// WAYLAND_DEBUG=1 or WAYLAND_DEBUG=client turns tracing on, like it does for libwayland
+pub fn trace_from_env() -> bool {
+    var("WAYLAND_DEBUG").is_ok_and(|debug| debug.contains('1') || debug.contains("client"))
+}
+
+/// Render a message the way libwayland's WAYLAND_DEBUG does, so traces can be diffed
+fn format_message(
+    objects: &ObjectMap,
+    sending: bool,
+    object: u32,
+    message: &MessageDesc,
+    body: &[u8],
+    fds: &[RawFd],
+) -> Result<String, DecodeErr> {
+    // libwayland uses the wall clock in microseconds, truncated to 32 bits
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u32;
+    let interface = objects.get(object).map_or("[unknown]", |object| object.interface.name);
+
+    let mut offset = 0;
+    let mut fds = fds.iter();
+    let mut args = Vec::with_capacity(message.args.len());
+    for arg in message.args {
+        let formatted = match arg.kind {
+            ArgKind::Int => body.read_i32(&mut offset)?.to_string(),
+            ArgKind::Uint => body.read_u32(&mut offset)?.to_string(),
+            ArgKind::Fixed => {
+                // Exactly libwayland's digits, 390625 is 1e8 / 256
+                let fixed = body.read_i32(&mut offset)?;
+                if fixed >= 0 {
+                    format!("{}.{:08}", fixed / 256, 390625 * (fixed % 256))
+                } else {
+                    format!("-{}.{:08}", fixed / -256, -390625 * (fixed % 256))
+                }
+            }
+            ArgKind::String => match body.read_opt_string(&mut offset)? {
+                Some(string) => format!("\"{}\"", string),
+                None => "nil".to_string(),
+            },
+            ArgKind::Object => match body.read_u32(&mut offset)? {
+                0 => "nil".to_string(),
+                id => format!("{}#{}", objects.get(id).map_or("[unknown]", |object| object.interface.name), id),
+            },
+            ArgKind::NewId => {
+                // Untyped new ids are preceded by the interface and version on the wire
+                if arg.interface.is_none() {
+                    args.push(format!("\"{}\"", body.read_string(&mut offset)?));
+                    args.push(body.read_u32(&mut offset)?.to_string());
+                }
+                format!("new id {}#{}", arg.interface.unwrap_or("[unknown]"), body.read_u32(&mut offset)?)
+            }
+            ArgKind::Array => format!("array[{}]", body.read_array(&mut offset)?.len()),
+            ArgKind::Fd => format!("fd {}", fds.next().map_or(-1, |fd| *fd)),
+        };
+        args.push(formatted);
+    }
+
+    Ok(format!(
+        "[{:7}.{:03}] {}{}#{}.{}({})",
+        time / 1000,
+        time % 1000,
+        if sending { " -> " } else { "" },
+        interface,
+        object,
+        message.name,
+        args.join(", ")
+    ))
+}
+
+impl WlClient {
+    /// Log a request about to be sent or an event just received, body is without header
+    pub fn trace(&self, sending: bool, object: u32, opcode: u16, body: &[u8], fds: &[RawFd]) {
+        let objects = self.objects.lock().unwrap();
+        let Some(interface) = objects.get(object).map(|object| object.interface) else {
+            eprintln!("[unknown]#{}.{}(...)", object, opcode);
+            return;
+        };
+        let messages = if sending { interface.requests } else { interface.events };
+        let Some(message) = messages.get(opcode as usize) else {
+            eprintln!("{}#{}.[unknown opcode {}](...)", interface.name, object, opcode);
+            return;
+        };
+        match format_message(&objects, sending, object, message, body, fds) {
+            Ok(line) => eprintln!("{}", line),
+            Err(err) => eprintln!("{}#{}.{}: {}", interface.name, object, message.name, err),
+        }
+    }
+}
@@ -1,74 +1,142 @@
+use std::{error::Error, fmt};
+
+#[derive(Debug)]
+pub struct DecodeErr (pub String);
+
+impl Error for DecodeErr {}
+impl fmt::Display for DecodeErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Failed to decode message: {}", self.0)
+    }
+}
+
+/// Size of a string on the wire: length prefix, contents, nul terminator and padding
+pub fn string_size(str: &str) -> usize {
+    4 + (str.len()+1).next_multiple_of(4)
+}
+
+/// Size of an array on the wire: length prefix, contents and padding
+pub fn array_size(array: &[u8]) -> usize {
+    4 + array.len().next_multiple_of(4)
+}
 
 pub trait WlMessage {
     /// Write a u32 to self at offset and increment offset by four
     fn write_u32(&mut self, value: &u32, offset: &mut usize);
-    /// Write a u16 to self at offset and increment offset by four
+    /// Write an i32 to self at offset and increment offset by four
+    fn write_i32(&mut self, value: &i32, offset: &mut usize);
+    /// Write a u16 to self at offset and increment offset by two
     fn write_u16(&mut self, value: &u16, offset: &mut usize);
     /// Write a string to self at offset
     /// and increment offset by string length rounded up to four bytes
-    fn write_string(&mut self, str: &String, offset: &mut usize);
+    fn write_string(&mut self, str: &str, offset: &mut usize);
+    /// Write a string or a null string (zero length) to self at offset
+    fn write_opt_string(&mut self, str: Option<&str>, offset: &mut usize);
+    /// Write an array to self at offset
+    /// and increment offset by array length rounded up to four bytes
+    fn write_array(&mut self, array: &[u8], offset: &mut usize);
     /// Read a u32 from self at offset and increment offset by four
-    fn read_u32(&self, offset: &mut usize) -> u32;
+    fn read_u32(&self, offset: &mut usize) -> Result<u32, DecodeErr>;
+    /// Read an i32 from self at offset and increment offset by four
+    fn read_i32(&self, offset: &mut usize) -> Result<i32, DecodeErr>;
     /// Read a u16 from self at offset and increment offset by two
-    fn read_u16(&self, offset: &mut usize) -> u16;
-    /// Read a string from self at offset 
+    fn read_u16(&self, offset: &mut usize) -> Result<u16, DecodeErr>;
+    /// Read a string from self at offset
     /// and increment offset by string length rounded up to four bytes
-    fn read_string(&self, offset: &mut usize) -> String;
+    fn read_string(&self, offset: &mut usize) -> Result<String, DecodeErr>;
+    /// Read a string from self at offset, a zero length means a null string
+    fn read_opt_string(&self, offset: &mut usize) -> Result<Option<String>, DecodeErr>;
+    /// Read an array from self at offset
+    /// and increment offset by array length rounded up to four bytes
+    fn read_array(&self, offset: &mut usize) -> Result<Vec<u8>, DecodeErr>;
 }
 
-impl WlMessage for Vec<u8> {
+impl WlMessage for [u8] {
     fn write_u32(&mut self, value: &u32, offset: &mut usize) {
         self[*offset..*offset+4].copy_from_slice(&value.to_ne_bytes());
         *offset += 4;
     }
 
+    fn write_i32(&mut self, value: &i32, offset: &mut usize) {
+        self[*offset..*offset+4].copy_from_slice(&value.to_ne_bytes());
+        *offset += 4;
+    }
+
     fn write_u16(&mut self, value: &u16, offset: &mut usize) {
         self[*offset..*offset+2].copy_from_slice(&value.to_ne_bytes());
         *offset += 2;
     }
 
-    fn write_string(&mut self, str: &String, offset: &mut usize) {
-        // TODO: Don't clone str, use &str instead of &String
-        let mut str = str.clone();
-        str.push('\0');
-        let rounded_len: u32 = (str.len()+3) as u32 & (u32::MAX-3);
-        self.write_u32(&rounded_len, offset);
+    fn write_string(&mut self, str: &str, offset: &mut usize) {
+        let len = str.len() as u32 + 1;
+        self.write_u32(&len, offset);
         self[*offset..*offset+str.len()].copy_from_slice(str.as_bytes());
-        *offset += rounded_len as usize;
+        self[*offset+str.len()] = 0;
+        *offset += (len as usize).next_multiple_of(4);
     }
 
-    fn read_u32(&self, offset: &mut usize) -> u32 {
+    fn write_opt_string(&mut self, str: Option<&str>, offset: &mut usize) {
+        match str {
+            Some(str) => self.write_string(str, offset),
+            None => self.write_u32(&0, offset),
+        }
+    }
+
+    fn write_array(&mut self, array: &[u8], offset: &mut usize) {
+        self.write_u32(&(array.len() as u32), offset);
+        self[*offset..*offset+array.len()].copy_from_slice(array);
+        *offset += array.len().next_multiple_of(4);
+    }
+
+    fn read_u32(&self, offset: &mut usize) -> Result<u32, DecodeErr> {
         let res = u32::from_ne_bytes(
-            self[*offset..*offset+4]
+            self.get(*offset..*offset+4)
+            .ok_or(DecodeErr("u32 out of bounds".to_string()))?
             .try_into()
-            .expect("u32::from_ne_bytes failed in WlEvent::read_u32")
+            .unwrap()
         );
         *offset += 4;
-        res
+        Ok(res)
     }
 
-    fn read_u16(&self, offset: &mut usize) -> u16 {
+    fn read_i32(&self, offset: &mut usize) -> Result<i32, DecodeErr> {
+        Ok(self.read_u32(offset)? as i32)
+    }
+
+    fn read_u16(&self, offset: &mut usize) -> Result<u16, DecodeErr> {
         let res = u16::from_ne_bytes(
-            self[*offset..*offset+2]
+            self.get(*offset..*offset+2)
+            .ok_or(DecodeErr("u16 out of bounds".to_string()))?
             .try_into()
-            .expect("u32::from_ne_bytes failed in WlEvent::read_u32")
+            .unwrap()
         );
         *offset += 2;
-        res
+        Ok(res)
     }
 
-    fn read_string(&self, offset: &mut usize) -> String {
-        let str_len = u32::from_ne_bytes(
-            self[*offset..*offset+4]
-            .try_into()
-            .expect("u32::from_ne_bytes failed in WlEvent::read_string")
-        );
-        *offset += 4;
-        let str = String::from_utf8(
-            self[*offset..*offset+((str_len-1) as usize)]
-            .to_vec()
-        ).expect("String::from_utf8 failed in WlEvent::read_string()");
-        *offset += (str_len+3 & u32::MAX-3) as usize;
-        str
+    fn read_string(&self, offset: &mut usize) -> Result<String, DecodeErr> {
+        self.read_opt_string(offset)?.ok_or(DecodeErr("unexpected null string".to_string()))
+    }
+
+    fn read_opt_string(&self, offset: &mut usize) -> Result<Option<String>, DecodeErr> {
+        let str_len = self.read_u32(offset)? as usize;
+        if str_len == 0 {
+            return Ok(None);
+        }
+        let bytes = self.get(*offset..*offset+str_len-1)
+            .ok_or(DecodeErr("string out of bounds".to_string()))?;
+        let str = String::from_utf8(bytes.to_vec())
+            .map_err(|_| DecodeErr("string is not utf-8".to_string()))?;
+        *offset += str_len.next_multiple_of(4);
+        Ok(Some(str))
+    }
+
+    fn read_array(&self, offset: &mut usize) -> Result<Vec<u8>, DecodeErr> {
+        let array_len = self.read_u32(offset)? as usize;
+        let array = self.get(*offset..*offset+array_len)
+            .ok_or(DecodeErr("array out of bounds".to_string()))?
+            .to_vec();
+        *offset += array_len.next_multiple_of(4);
+        Ok(array)
     }
 }
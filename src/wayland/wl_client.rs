@@ -1,215 +1,244 @@
-use std::{collections::HashMap, env::var, error::Error, fmt::Debug, io::{IoSliceMut, Write}, os::unix::net::{AncillaryData, SocketAncillary, UnixStream}, sync::{atomic::{AtomicBool, AtomicU32, Ordering}, mpsc, Arc, Mutex, RwLock}, thread::{self}, u32};
+use std::{collections::HashMap, env::var, error::Error, fmt::Debug, io, os::{fd::{AsRawFd, RawFd}, unix::net::UnixStream}, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex, RwLock}};
 
-use crate::{graphics::{circle::Circle, drawable::{premultiply, Drawable}, rectangle::Rectangle}, wayland::{shm, surface::UnsetErr, vec_utils::WlMessage, wl_shm::wl_buffer}};
+use libc::{EPOLLIN, EPOLLOUT, SIGCHLD, SIGINT, SIGTERM};
 
-struct WlHeader {
-    object: u32,
-    opcode: u16,
-    size:   u16,
-}
+use crate::{config::Config, graphics::drawable::Drawable, wayland::{connection::Connection, trace::trace_from_env, event_loop::{block_signals, EventLoop}, object_map::{Handler, Object, ObjectMap}, protocol::{Interface, Message, MessageDesc}, output::Output, pointer::Pointer, seat::KeyRepeat, shm, surface::{Surface, UnsetErr}, touch::Touch}};
 
 pub struct WlClient {
-    pub socket:             Mutex<UnixStream>,
-    pub current_id:         AtomicU32,
+    pub connection:         Mutex<Connection>,
+    pub objects:            Mutex<ObjectMap>,
     pub running:            AtomicBool,
-    pub shm_pool:           Mutex<shm::ShmPool>,
-    pub active_buffer:      AtomicBool,
-    pub buffer1:            Mutex<Option<wl_buffer>>,
-    pub buffer2:            Mutex<Option<wl_buffer>>,
-    pub keymap_fd:          Mutex<Option<shm::ShmPool>>,
+    pub surfaces:           Mutex<Vec<Surface>>,
+    pub outputs:            Mutex<Vec<Output>>,
+    /// Set once init_toplevel made the first surfaces
+    pub started:            AtomicBool,
+    /// Pixel formats wl_shm advertised
+    pub shm_formats:        Mutex<Vec<u32>>,
+    pub keymap_fd:          Mutex<Option<shm::ShmMap>>,
     pub keymap:             RwLock<Option<HashMap<u32, Vec<String>>>>,
-    pub drawables:          Mutex<Vec<Box<dyn Drawable>>>,
-
-    pub registry_id:        AtomicU32,
-    pub shm_id:             AtomicU32,
-    pub shmpool_id:         AtomicU32,
-    pub seat_id:            AtomicU32,
-    pub keyboard_id:        AtomicU32,
-    pub compositor_id:      AtomicU32,
-    pub surface_id:         AtomicU32,
-    pub xdg_wm_base_id:     AtomicU32,
-    pub layer_shell_id:     AtomicU32,
-    pub layer_surface_id:   AtomicU32,
-    pub frame_hint_id:      AtomicU32,
+    /// Scene of every surface, by wl_surface id
+    pub drawables:          Mutex<HashMap<u32, Vec<Box<dyn Drawable>>>>,
+    pub event_loop:         Mutex<EventLoop>,
+    pub wayland_source:     u64,
+    pub key_repeat:         Mutex<KeyRepeat>,
+    /// Surface the keyboard is focused on, keys only arrive while there is one
+    pub keyboard_focus:     Mutex<Option<u32>>,
+    pub pointer:            Mutex<Pointer>,
+    pub touch:              Mutex<Touch>,
+    pub tracing:            bool,
+    pub config:             Config,
 }
 
 impl WlClient {
-    pub fn run() -> Result<(), Box<dyn Error>> {
+    pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
+        // Before anything else runs so no thread ends up with them unblocked
+        let signals = block_signals(&[SIGINT, SIGTERM, SIGCHLD])?;
+
         let sock = UnixStream::connect(format!(
             "{}/{}",
             var("XDG_RUNTIME_DIR")?,
             var("WAYLAND_DISPLAY")?
         ))?;
+        let arc_wl_client = WlClient::new(sock, config)?;
+        arc_wl_client.event_loop.lock().unwrap().add_signals(&signals, |client, signal| match signal {
+            SIGCHLD => client.reap_children(),
+            _ => client.exit(),
+        })?;
+        // Before start, so a second launch exits without ever showing a surface
+        let Some(_ipc) = arc_wl_client.ipc_listen()? else {
+            return Ok(());
+        };
+        arc_wl_client.start()?;
+
+        let result = arc_wl_client.dispatch_until_exit();
+        // Nothing is running anymore however the loop ended, the error goes up to main
+        arc_wl_client.running.store(false, Ordering::Relaxed);
+        result
+    }
+
+    /// Client on a connected socket, nothing is sent until start
+    pub fn new(sock: UnixStream, config: Config) -> Result<Arc<Self>, Box<dyn Error>> {
         sock.set_nonblocking(true)?;
+        let mut event_loop = EventLoop::new()?;
+        let wayland_source = event_loop.add_fd(sock.as_raw_fd(), EPOLLIN as u32, |client, _, _| client.read_events())?;
 
-        let mut arc_wl_client = Arc::new(WlClient {
-            socket:             Mutex::new(sock),
+        let arc_wl_client = Arc::new(WlClient {
+            connection:         Mutex::new(Connection::new(sock)),
+            objects:            Mutex::new(ObjectMap::new()),
             running:            AtomicBool::from(false),
-            current_id:         AtomicU32::from(1),
-            registry_id:        AtomicU32::from(0),
-            shm_id:             AtomicU32::from(0),
-            shmpool_id:         AtomicU32::from(0),
-            shm_pool:           Mutex::new(shm::ShmPool::new(800 * 800 * 4 * 2)?),
-            active_buffer:      AtomicBool::from(false),
-            buffer1:            Mutex::new(None),
-            buffer2:            Mutex::new(None),
-            frame_hint_id:      AtomicU32::from(0),
-            compositor_id:      AtomicU32::from(0),
-            surface_id:         AtomicU32::from(0),
-            xdg_wm_base_id:     AtomicU32::from(0),
-            layer_shell_id:     AtomicU32::from(0),
-            layer_surface_id:   AtomicU32::from(0),
-            seat_id:            AtomicU32::from(0),
-            keyboard_id:        AtomicU32::from(0),
+            surfaces:           Mutex::new(Vec::new()),
+            outputs:            Mutex::new(Vec::new()),
+            started:            AtomicBool::from(false),
+            shm_formats:        Mutex::new(Vec::new()),
             keymap:             RwLock::new(None),
             keymap_fd:          Mutex::new(None),
-            drawables:          Mutex::new(Vec::new()),
-        }); 
-        arc_wl_client.wl_display_get_registry();
-        if let Ok(mut drawables) = arc_wl_client.drawables.lock() {
-            drawables.push(Rectangle::new(50, 50, 300, 300, 16, 0xffff8800).into());
-            drawables.push(Rectangle::new(350, 50, 300, 300, 16, premultiply(0x77aa22aa)).into());
-            drawables.push(Circle::new(350, 80, 25, premultiply(0xff00ffff)).into());
-            drawables.push(Circle::new(350, 160, 25, premultiply(0x8800ffff)).into());
-            drawables.push(Circle::new(350, 240, 25, premultiply(0x0000ffff)).into());
-        }
-        arc_wl_client.running.store(true, Ordering::Relaxed);
-
-        let wl_client = arc_wl_client.clone();
-        let readloop = thread::Builder::new().name("readloop".to_string()).spawn(move || {
-            while wl_client.running.load(Ordering::Relaxed) {
-                wl_client.read_event();
-            }
-        })?;
+            drawables:          Mutex::new(HashMap::new()),
+            event_loop:         Mutex::new(event_loop),
+            wayland_source,
+            key_repeat:         Mutex::new(KeyRepeat::default()),
+            keyboard_focus:     Mutex::new(None),
+            pointer:            Mutex::new(Pointer::default()),
+            touch:              Mutex::new(Touch::default()),
+            tracing:            config.trace || trace_from_env(),
+            config,
+        });
+        arc_wl_client.objects.lock().unwrap().set_handler(1, Arc::new(
+            |client, id, opcode, body, fds| client.wl_display_event(id, Message::decode(opcode, body, fds)?)
+        ));
+
+        Ok(arc_wl_client)
+    }
 
-        readloop.join();
+    /// Bind the globals and create the launcher surface
+    pub fn start(self: &Arc<Self>) -> Result<(), Box<dyn Error>> {
+        self.running.store(true, Ordering::Relaxed);
 
-        Ok(())
+        // All globals are announced and bound once the compositor answers the sync,
+        // outputs describe themselves in reply to the binds
+        self.wl_display_get_registry()?;
+        self.roundtrip()?;
+        self.roundtrip()?;
+        self.init_toplevel()
     }
 
-    pub fn read_event(self: &Arc<Self>) -> Result<(), Box<dyn Error + '_>> {
-        // TODO: Don't realloc header and event
-
-        // FIXME: Using fd like this is unreliable because fd could be before or after
-        // event it was intended to be with
-        let mut fd = 0;
-
-        let mut header = vec![0u8; 8];
-        let socket = self.socket.lock().unwrap();
-        let mut ancillary_buf = [0; 128];
-        let mut ancillary = SocketAncillary::new(&mut ancillary_buf);
-
-        socket.recv_vectored_with_ancillary(
-            &mut [IoSliceMut::new(header.as_mut_slice())],
-            &mut ancillary
-        )?;
-        for ancillary_result in ancillary.messages() {
-            if let AncillaryData::ScmRights(scm_rights) = ancillary_result.unwrap() {
-                scm_rights.for_each(|received| {
-                    fd = received;
-                });
+    /// Run the event loop until exit is called or a source fails
+    fn dispatch_until_exit(self: &Arc<Self>) -> Result<(), Box<dyn Error>> {
+        let mut writing = false;
+        while self.running.load(Ordering::Relaxed) {
+            self.flush()?;
+            // Only wake up for writability while the compositor is behind on reading
+            let queued = self.connection.lock().unwrap().has_queued();
+            if queued != writing {
+                let events = if queued { EPOLLIN | EPOLLOUT } else { EPOLLIN };
+                self.event_loop.lock().unwrap().modify(self.wayland_source, events as u32)?;
+                writing = queued;
             }
+            self.dispatch_sources(None)?;
         }
 
-        let header = WlHeader {
-            object: u32::from_ne_bytes(header[0..4].try_into()?),
-            opcode: u16::from_ne_bytes(header[4..6].try_into()?),
-            size:   u16::from_ne_bytes(header[6..8].try_into()?)
-        };
+        Ok(())
+    }
 
-        let mut event = vec![0u8; header.size as usize - 8];
-        socket.recv_vectored_with_ancillary(
-            &mut [IoSliceMut::new(event.as_mut_slice())],
-            &mut ancillary
-        )?;
-        for ancillary_result in ancillary.messages() {
-            if let AncillaryData::ScmRights(scm_rights) = ancillary_result.unwrap() {
-                scm_rights.for_each(|received| {
-                    fd = received;
-                });
-            }
-        }
+    /// Allocate an id for a new object, its events are decoded and passed to handler
+    pub fn new_object<E, F>(&self, handler: F) -> u32
+    where
+        E: Message + 'static,
+        F: Fn(&Arc<WlClient>, u32, E) -> Result<(), Box<dyn Error>> + Send + Sync + 'static,
+    {
+        let handler: Handler = Arc::new(move |client, id, opcode, body, fds| {
+            handler(client, id, E::decode(opcode, body, fds)?)
+        });
+        self.objects.lock().unwrap().allocate(Object {
+            interface:  E::INTERFACE,
+            handler:    Some(handler),
+            version:    1,
+            zombie:     false,
+        })
+    }
 
-        drop(socket);
+    /// Allocate an id for a new object whose events are ignored
+    pub fn new_object_ignored(&self, interface: &'static Interface) -> u32 {
+        self.objects.lock().unwrap().allocate(Object {
+            interface,
+            handler:    None,
+            version:    1,
+            zombie:     false,
+        })
+    }
 
-        if let Some(buffer) = self.buffer1.lock()?.as_mut() {
-            if header.object == buffer.id && header.opcode == 0 { // wl_buffer::release
+    /// Id of the bound global implementing interface
+    pub fn global(&self, interface: &Interface) -> Result<u32, UnsetErr> {
+        self.objects.lock().unwrap().find(interface).ok_or(UnsetErr(interface.name.to_string()))
+    }
 
-            }
-        }
+    /// Version the global implementing interface was bound with, 0 if it wasn't
+    pub fn global_version(&self, interface: &Interface) -> u32 {
+        let objects = self.objects.lock().unwrap();
+        objects.find(interface).and_then(|id| objects.get(id)).map_or(0, |object| object.version)
+    }
 
-        if header.object == self.registry_id.load(Ordering::Relaxed) && header.opcode == 0 { // wl_registry::global
-            self.wl_registry_global(&event)?;
-        }
-        else if header.object == 1 && header.opcode == 0 { // wl_display::error
-            WlClient::wl_display_error(&event);
-        }
-        else if header.object == self.shm_id.load(Ordering::Relaxed) && header.opcode == 0 { // wl_shm::format
-            WlClient::wl_shm_format(&event);
-        }
-        else if header.object == self.xdg_wm_base_id.load(Ordering::Relaxed) && header.opcode == 0 { // xdg_wm_base::ping
-            self.xdg_wm_base_pong(&event)?;
-        }
-        else if header.object == self.layer_surface_id.load(Ordering::Relaxed) && header.opcode == 0 { // zwlr_layer_surface::configure
-            self.layer_surface_configure(&event)?;
-        }
-        else if header.object == self.surface_id.load(Ordering::Relaxed) && header.opcode == 2 { // wl_surface::preferred_buffer_scale
-            // println!("Preferred buffer scale: {}", i32::from_ne_bytes(event[0..4].try_into().unwrap()));
-        }
-        else if header.object == self.surface_id.load(Ordering::Relaxed) && header.opcode == 3 { // wl_surface::preferred_buffer_transform
-            // println!("Preferred buffer transform: {}", i32::from_ne_bytes(event[0..4].try_into().unwrap()));
-        }
-        else if header.object == self.seat_id.load(Ordering::Relaxed) && header.opcode == 0 { // wl_seat::capabilities
-            self.wl_seat_capabilities(&event)?;
-        }
-        else if header.object == self.seat_id.load(Ordering::Relaxed) && header.opcode == 1 { // wl_seat::name
-            self.wl_seat_name(&event);
-        }
-        else if header.object == self.keyboard_id.load(Ordering::Relaxed) && header.opcode == 0 { // wl_keyboard::keymap
-            self.wl_keyboard_keymap(&event, fd)?;
+    /// Serialize request for object and queue it, it is written on the next flush
+    pub fn send<M: Message>(&self, object: u32, request: M) -> Result<(), Box<dyn Error>> {
+        let mut fds = Vec::new();
+        let opcode = request.opcode();
+        let request = request.encode(object, &mut fds);
+        if self.tracing {
+            let raw_fds: Vec<RawFd> = fds.iter().map(|fd| fd.as_raw_fd()).collect();
+            self.trace(true, object, opcode, &request[8..], &raw_fds);
         }
-        else if header.object == self.keyboard_id.load(Ordering::Relaxed) && header.opcode == 3 { // wl_keyboard::key
-            let wl_client = self.clone();
-            thread::spawn(move || {
-                wl_client.wl_keyboard_key(&event);
-            });
+        self.connection.lock().unwrap().queue(&request, fds)?;
+        if M::INTERFACE.requests.get(opcode as usize).is_some_and(|desc| desc.destructor) {
+            self.objects.lock().unwrap().destroy(object);
         }
-        else if header.object == self.frame_hint_id.load(Ordering::Relaxed) && header.opcode == 0 { // wl_callback<frame_hint>::done
-            self.wl_surface_frame()?;
-        }
-        else {
-            println!(
-                "Received event:\n\tObject: {}\n\tOpcode: {}\n\tSize: {}",
-                header.object,
-                header.opcode,
-                header.size
-            );
-        }
-
         Ok(())
     }
 
-    pub fn destroy_object(&self, id: u32, opcode: u16) {
-        if id == 0 {
-            return;
+    /// Write queued requests, what the socket doesn't take stays queued for the next flush
+    pub fn flush(&self) -> Result<(), Box<dyn Error>> {
+        match self.connection.lock().unwrap().flush() {
+            Err(err) if err.kind() != io::ErrorKind::WouldBlock => Err(err.into()),
+            _ => Ok(()),
         }
-        const REQ_SIZE: u16 = 8;
-
-        let mut request = vec![0; REQ_SIZE as usize];
-        let mut offset = 0;
+    }
 
-        request.write_u32(&id,       &mut offset);
-        request.write_u16(&opcode,   &mut offset);
-        request.write_u16(&REQ_SIZE, &mut offset);
+    /// Read what the compositor sent and dispatch every complete event
+    pub fn read_events(self: &Arc<Self>) -> Result<(), Box<dyn Error>> {
+        match self.connection.lock().unwrap().read() {
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
+        self.dispatch_events()
+    }
 
-        self.socket.lock().unwrap().write(&request);
-        self.shmpool_id.store(0, Ordering::Relaxed);
+    /// Dispatch the complete events in the connection buffer
+    pub fn dispatch_events(self: &Arc<Self>) -> Result<(), Box<dyn Error>> {
+        loop {
+            let mut connection = self.connection.lock().unwrap();
+            let Some(header) = connection.peek_header()? else {
+                return Ok(());
+            };
+
+            // Don't hold the object map while handling, handlers create objects.
+            // Zombies have no handler, their events are only read to take their fds
+            let (fd_count, handler) = match self.objects.lock().unwrap().get(header.object) {
+                Some(object) => (
+                    object.interface.events.get(header.opcode as usize).map_or(0, MessageDesc::fd_count),
+                    object.handler.clone(),
+                ),
+                None => {
+                    eprintln!(
+                        "Received event for unknown object:\n\tObject: {}\n\tOpcode: {}\n\tSize: {}",
+                        header.object,
+                        header.opcode,
+                        header.size
+                    );
+                    (0, None)
+                }
+            };
+
+            let Some(mut event) = connection.next_message(fd_count)? else {
+                return Ok(());
+            };
+            drop(connection);
+
+            if self.tracing {
+                let raw_fds: Vec<RawFd> = event.fds.iter().map(|fd| fd.as_raw_fd()).collect();
+                self.trace(false, event.header.object, event.header.opcode, &event.body, &raw_fds);
+            }
+            if let Some(handler) = handler {
+                handler(self, event.header.object, event.header.opcode, &event.body, &mut event.fds)?;
+            }
+        }
     }
 
-    pub fn exit(&self) -> Result<(), Box<dyn Error + '_>> {
+    pub fn exit(&self) -> Result<(), Box<dyn Error>> {
         println!("Exiting!");
-        self.destroy_object(self.layer_surface_id.load(Ordering::Relaxed), 7);
-        self.destroy_object(self.buffer1.lock()?.as_ref().ok_or(UnsetErr("buffer1".to_string()))?.id, 0);
+        let surfaces: Vec<u32> = self.surfaces.lock().unwrap().iter().map(|surface| surface.wl_surface).collect();
+        for wl_surface in surfaces {
+            self.destroy_surface(wl_surface)?;
+        }
+        self.connection.lock().unwrap().flush_blocking()?;
         self.keymap_fd.lock().unwrap().take();
         self.running.store(false, Ordering::Relaxed);
         Ok(())
@@ -218,34 +247,9 @@ impl WlClient {
 
 impl Debug for WlClient {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "WlClient {{
-    current_id: {},
-    registry_id: {},
-    shm_id: {},
-    shmpool_id: {},
-    buffer1: {:?},
-    buffer2: {:?},
-    compositor_id: {},
-    surface_id: {},
-    xdg_wm_base_id: {},
-    layer_shell_id: {},
-    layer_surface_id: {},
-    seat_id: {},
-    keyboard_id: {},
-}}",
-    self.current_id.load(Ordering::Relaxed),
-    self.registry_id.load(Ordering::Relaxed),
-    self.shm_id.load(Ordering::Relaxed),
-    self.shmpool_id.load(Ordering::Relaxed),
-    self.buffer1.lock().unwrap(),
-    self.buffer2.lock().unwrap(),
-    self.compositor_id.load(Ordering::Relaxed),
-    self.surface_id.load(Ordering::Relaxed),
-    self.xdg_wm_base_id.load(Ordering::Relaxed),
-    self.layer_shell_id.load(Ordering::Relaxed),
-    self.layer_surface_id.load(Ordering::Relaxed),
-    self.seat_id.load(Ordering::Relaxed),
-    self.keyboard_id.load(Ordering::Relaxed),
-        )
+        f.debug_struct("WlClient")
+            .field("objects", &*self.objects.lock().unwrap())
+            .field("surfaces", &*self.surfaces.lock().unwrap())
+            .finish()
     }
 }
@@ -0,0 +1,83 @@
+use std::{error::Error, fmt::Display, sync::{atomic::{AtomicBool, Ordering}, Arc}};
+
+use crate::wayland::{protocol::{wl_callback, wl_display, ErrorCode}, wl_client::WlClient};
+
+/// Fatal error the compositor raised on one of our objects, the connection is dead afterwards
+#[derive(Debug)]
+pub struct ProtocolError {
+    pub interface:  &'static str,
+    pub object:     u32,
+    pub code:       ErrorCode,
+    pub message:    String,
+}
+
+impl Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Protocol error on {}@{}: {} ({}): {}",
+            self.interface,
+            self.object,
+            self.code,
+            self.code.code(),
+            self.message
+        )
+    }
+}
+
+impl Error for ProtocolError {}
+
+impl WlClient {
+    pub fn wl_display_event(&self, _display: u32, event: wl_display::Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            wl_display::Event::Error { object_id, code, message } => {
+                Err(self.wl_display_error(object_id, code, message).into())
+            }
+            wl_display::Event::DeleteId { id } => {
+                self.objects.lock().unwrap().release(id);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn wl_display_error(&self, object: u32, code: u32, message: String) -> ProtocolError {
+        let interface = self.objects.lock().unwrap().get(object)
+            .map_or("unknown", |object| object.interface.name);
+        ProtocolError {
+            interface,
+            object,
+            code: ErrorCode::new(interface, code),
+            message,
+        }
+    }
+
+    /// Have done called once the compositor has processed every request sent before
+    pub fn wl_display_sync<F>(&self, done: F) -> Result<u32, Box<dyn Error>>
+    where
+        F: Fn(&Arc<WlClient>) -> Result<(), Box<dyn Error>> + Send + Sync + 'static,
+    {
+        // The compositor destroys the callback after done, its id comes back with delete_id
+        let callback = self.new_object(move |client, _, event| match event {
+            wl_callback::Event::Done { .. } => done(client),
+        });
+        self.send(1, wl_display::Request::Sync { callback })?;
+        Ok(callback)
+    }
+
+    /// Block until the compositor has processed every request sent so far
+    /// Events that arrive in the meantime are dispatched as usual
+    pub fn roundtrip(self: &Arc<Self>) -> Result<(), Box<dyn Error>> {
+        let done = Arc::new(AtomicBool::new(false));
+        let synced = done.clone();
+        self.wl_display_sync(move |_| {
+            synced.store(true, Ordering::Relaxed);
+            Ok(())
+        })?;
+
+        while !done.load(Ordering::Relaxed) && self.running.load(Ordering::Relaxed) {
+            self.connection.lock().unwrap().flush_blocking()?;
+            self.dispatch_sources(None)?;
+        }
+        Ok(())
+    }
+}
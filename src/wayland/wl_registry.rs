@@ -1,84 +1,90 @@
-use crate::wayland::{surface::UnsetErr, vec_utils::WlMessage, wl_client::WlClient, wl_shm::wl_buffer};
-use std::{error::Error, io::Write, sync::{atomic::{AtomicU32, Ordering}, Arc}};
+use crate::{graphics::renderer::demo_scene, wayland::{protocol::{wl_compositor, wl_display, wl_output, wl_registry, wl_seat, wl_shm, wp_fractional_scale_manager_v1, wp_viewporter, xdg_wm_base, zwlr_layer_shell_v1, zxdg_output_manager_v1}, shm::ShmPool, surface::{Surface, UnsetErr, BUFFER_COUNT}, swapchain::Swapchain, wl_client::WlClient}};
+use std::{error::Error, sync::atomic::Ordering};
 
 impl WlClient {
-    fn init_toplevel(&self) -> Result<(), Box<dyn Error + '_>> {
-        if self.shm_id.load(Ordering::Relaxed) == 0 {
-            return Err(Box::new(UnsetErr("shm_id".to_string())));
-        }
-        if self.compositor_id.load(Ordering::Relaxed) == 0 {
-            return Err(Box::new(UnsetErr("compositor_id".to_string())));
-        }
-        if self.xdg_wm_base_id.load(Ordering::Relaxed) == 0 {
-            return Err(Box::new(UnsetErr("xdg_wm_base_id".to_string())));
-        }
-        if self.layer_shell_id.load(Ordering::Relaxed) == 0 {
-            return Err(UnsetErr("layer_shell_id".to_string()).into());
-        }
-        if self.seat_id.load(Ordering::Relaxed) == 0 {
-            return Err(UnsetErr("seat_id".to_string()).into());
-        }
+    pub fn init_toplevel(&self) -> Result<(), Box<dyn Error>> {
+        self.global(&wl_shm::INTERFACE)?;
+        self.global(&wl_compositor::INTERFACE)?;
+        self.global(&wl_seat::INTERFACE)?;
         println!("Initializing toplevel!");
-        self.wl_compositor_create_surface()?;
-        self.layer_shell_get_layer_surface()?;
-
-        self.layer_surface_set_size(800, 800)?;
-        self.layer_surface_set_keyboard_interactivity()?;
-        self.wl_surface_commit()?;
-
-        self.wl_shm_create_pool()?;
-
-        let current_id = self.current_id.fetch_add(2, Ordering::Relaxed);
-        let mut buffer1 = self.buffer1.lock().unwrap();
-        let mut buffer2 = self.buffer2.lock().unwrap();
-        *buffer1 = Some(wl_buffer {
-            id:     current_id + 1,
-            offset: 0,
-            width:  800,
-            height: 800,
-            ready:  true,
-        });
-        *buffer2 = Some(wl_buffer {
-            id:     current_id + 2,
-            offset: 800 * 800, // pixel offset in pool
-            width:  800,
-            height: 800,
-            ready:  true,
-        });
-        self.wl_shm_pool_create_buffer(buffer1.as_ref().unwrap())?;
-        self.wl_shm_pool_create_buffer(buffer2.as_ref().unwrap())?;
+        println!("Pixel format: {:?}", self.shm_format());
+
+        // A window can't be put on an output, it only ever gets one surface
+        if self.global(&zwlr_layer_shell_v1::INTERFACE).is_ok() {
+            for output in self.chosen_outputs() {
+                self.init_surface(output)?;
+            }
+        } else {
+            self.init_surface(None)?;
+        }
+        self.started.store(true, Ordering::Relaxed);
 
         Ok(())
     }
 
-    pub fn wl_display_get_registry(&self) -> Result<(), Box<dyn Error>> {
-        const OBJECT: u32 = 1;
-        const OPCODE: u16 = 1;
-        const MSG_SIZE: u16 = 12;
-
-        let mut request = vec![0u8; MSG_SIZE as usize];
-        let mut offset: usize = 0;
-
-        request.write_u32(&OBJECT,   &mut offset);
-        request.write_u16(&OPCODE,   &mut offset);
-        request.write_u16(&MSG_SIZE, &mut offset);
-
-        let current_id = self.current_id.fetch_add(1, Ordering::Relaxed) + 1;
-        request.write_u32(&current_id, &mut offset);
-
-        self.socket.lock().unwrap().write(&request)?;
-        self.registry_id.store(current_id, Ordering::Relaxed);
+    /// Create one launcher surface, on output when there is layer shell
+    pub fn init_surface(&self, output: Option<u32>) -> Result<(), Box<dyn Error>> {
+        let wl_surface = self.wl_compositor_create_surface()?;
+
+        // Without layer shell (e.g. on Mutter) the launcher becomes a regular window
+        let (role, output) = if self.global(&zwlr_layer_shell_v1::INTERFACE).is_ok() {
+            (self.init_layer_surface(wl_surface, output)?, output)
+        } else {
+            self.global(&xdg_wm_base::INTERFACE)?;
+            println!("No layer shell, falling back to xdg_toplevel");
+            (self.init_xdg_toplevel(wl_surface)?, None)
+        };
+        let fractional = self.init_fractional_scale(wl_surface)?;
+        self.wl_surface_commit(wl_surface)?;
+
+        // Buffers are made once the first configure tells the size, a stretched
+        // surface has none yet so the pool starts at a page and grows then
+        let placement = &self.config.placement;
+        let size = placement.width as usize * placement.height as usize * 4 * BUFFER_COUNT;
+        let shm_pool = ShmPool::new(size.max(4096))?;
+        let wl_shm_pool = self.wl_shm_create_pool(&shm_pool)?;
+
+        self.surfaces.lock().unwrap().push(Surface {
+            wl_surface,
+            role,
+            output,
+            shm_pool,
+            wl_shm_pool,
+            swapchain:      Swapchain::default(),
+            width:          0,
+            height:         0,
+            scale:          1.0,
+            fractional,
+            entered:        Vec::new(),
+            pending_size:   (0, 0),
+            frame_callback: None,
+            redraw:         false,
+            damage:         Vec::new(),
+        });
+        // Laid out for the size the first configure brings
+        self.surface_set_drawables(wl_surface, demo_scene(0, 0))?;
 
         Ok(())
     }
 
-    pub fn wl_registry_global(&self, event: &Vec<u8>) -> Result<(), Box<dyn Error>> {
-        let mut offset: usize = 0;
+    pub fn wl_display_get_registry(&self) -> Result<u32, Box<dyn Error>> {
+        let registry = self.new_object(|client, registry, event| client.wl_registry_event(registry, event));
+        self.send(1, wl_display::Request::GetRegistry { registry })?;
+
+        Ok(registry)
+    }
 
-        let name        = event.read_u32(&mut offset);
-        let interface   = event.read_string(&mut offset);
-        let version     = event.read_u32(&mut offset);
+    pub fn wl_registry_event(&self, registry: u32, event: wl_registry::Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            wl_registry::Event::Global { name, interface, version } => {
+                self.wl_registry_global(registry, name, &interface, version)
+            }
+            // Only outputs come and go, the other globals stay for the whole session
+            wl_registry::Event::GlobalRemove { name } => self.wl_output_remove(name),
+        }
+    }
 
+    pub fn wl_registry_global(&self, registry: u32, name: u32, interface: &str, version: u32) -> Result<(), Box<dyn Error>> {
         // println!(
         //     "Received global:\n\tName: {}\n\tInterface: {}\n\tVersion: {}",
         //     name,
@@ -86,72 +92,67 @@ impl WlClient {
         //     version,
         // );
 
-        macro_rules! bind_global {
-            ($global:tt) => {
-                let current_id = self.current_id.fetch_add(1, Ordering::Relaxed) + 1;
-                self.wl_registry_bind(&name, &interface, &version, &current_id)?;
-                self.$global.store(current_id, Ordering::Relaxed);
-                self.init_toplevel().unwrap_or_else(|err| {eprintln!("{}", err)});
-            };
+        let id = if interface == wl_shm::NAME {
+            self.new_object(|client, _, event| client.wl_shm_event(event))
         }
-
-        if interface == "wl_shm" {
-            bind_global!(shm_id);
+        else if interface == wl_compositor::NAME {
+            self.new_object_ignored(&wl_compositor::INTERFACE)
         }
-        else if interface == "wl_compositor" {
-            bind_global!(compositor_id);
+        else if interface == xdg_wm_base::NAME {
+            self.new_object(|client, id, event| client.xdg_wm_base_event(id, event))
         }
-        else if interface == "xdg_wm_base" {
-            bind_global!(xdg_wm_base_id);
+        else if interface == zwlr_layer_shell_v1::NAME {
+            self.new_object_ignored(&zwlr_layer_shell_v1::INTERFACE)
         }
-        else if interface == "zwlr_layer_shell_v1" {
-            bind_global!(layer_shell_id);
+        else if interface == wl_seat::NAME {
+            self.new_object(|client, id, event| client.wl_seat_event(id, event))
         }
-        else if interface == "wl_seat" {
-            bind_global!(seat_id);
+        else if interface == wl_output::NAME {
+            self.wl_output_new(name)
+        }
+        else if interface == zxdg_output_manager_v1::NAME {
+            self.new_object_ignored(&zxdg_output_manager_v1::INTERFACE)
+        }
+        else if interface == wp_fractional_scale_manager_v1::NAME {
+            self.new_object_ignored(&wp_fractional_scale_manager_v1::INTERFACE)
+        }
+        else if interface == wp_viewporter::NAME {
+            self.new_object_ignored(&wp_viewporter::INTERFACE)
+        }
+        else {
+            return Ok(());
+        };
+
+        self.wl_registry_bind(registry, name, version, id)?;
+        // Outputs and the manager can come in any order, whichever is last gets the xdg_outputs
+        if interface == wl_output::NAME || interface == zxdg_output_manager_v1::NAME {
+            self.xdg_output_manager_get_outputs()?;
         }
-
         Ok(())
     }
 
+    /// Bind global name to the object id was allocated for
     pub fn wl_registry_bind(
         &self,
-        name: &u32,
-        interface: &String,
-        version: &u32,
-        id: &u32
+        registry: u32,
+        name: u32,
+        version: u32,
+        id: u32
     ) -> Result<(), Box<dyn Error>> {
-        let object: u32 = self.registry_id.load(Ordering::Relaxed);
-        if object == 0 {
-            return Err(UnsetErr("registry_id".to_string()).into());
-        }
-        const OPCODE: u16 = 0;
-
-        let req_size: u16 = 24 + ((interface.len() as u16+3) & (u16::MAX-3));
-        let mut request = vec![0u8; req_size as usize];
-        let mut offset: usize = 0;
-
-        request.write_u32    (&object,    &mut offset);
-        request.write_u16    (&OPCODE,    &mut offset);
-        request.write_u16    (&req_size,  &mut offset);
-
-        request.write_u32    (&name,      &mut offset);
-        request.write_string (&interface, &mut offset);
-        request.write_u32    (&version,   &mut offset);
-        request.write_u32    (&id,        &mut offset);
-
-        self.socket.lock().unwrap().write(&request)?;
-
-        Ok(())
-    }
-
-    pub fn wl_display_error(event: &Vec<u8>) {
-        let mut offset: usize = 0;
-        eprintln!(
-            "Received error:\n\tObject: {}\n\tCode: {}\n\tMessage: {}",
-            event.read_u32(&mut offset),
-            event.read_u32(&mut offset),
-            event.read_string(&mut offset)
-        );
+        let mut objects = self.objects.lock().unwrap();
+        let interface = objects.get(id)
+            .ok_or(UnsetErr(format!("object {}", id)))?
+            .interface;
+        // Never bind a newer version than the bindings were generated for
+        let version = version.min(interface.version);
+        objects.set_version(id, version);
+        drop(objects);
+
+        self.send(registry, wl_registry::Request::Bind {
+            name,
+            interface: interface.name.to_string(),
+            version,
+            id,
+        })
     }
 }
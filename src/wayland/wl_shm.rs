@@ -1,97 +1,138 @@
-use std::sync::atomic::AtomicU32;
-use std::{error::Error, io::Write, os::unix::net::SocketAncillary, sync::atomic::Ordering, u8};
-use crate::wayland::{surface::UnsetErr, vec_utils::WlMessage, wl_client::WlClient};
-
-const STRIDE: usize = 4;
+use std::error::Error;
+use crate::{graphics::{canvas::{Canvas, OutOfBoundsErr}, format::PixelFormat}, wayland::{protocol::{wl_shm, wl_shm_pool}, shm::ShmPool, wl_client::WlClient}};
 
 #[derive(Debug)]
-pub struct wl_buffer {
+pub struct WlBuffer {
     pub id:         u32,
     pub offset:     usize,
     pub width:      usize,
     pub height:     usize,
-    pub ready:      bool,
+    /// Attached and not released yet, the compositor may be reading it
+    pub busy:       bool,
+    /// Frames since the buffer was drawn into, 0 while its contents are undefined
+    pub age:        usize,
+    pub format:     PixelFormat,
 }
 
-impl WlClient {
-    pub fn wl_shm_format(event: &Vec<u8>) {
-        // let mut offset = 0;
-        // println!("Received pixel format: {:x}", event.read_u32(&mut offset));
-    }
+/// Canvas over the part of a pool a buffer covers
+pub struct ShmCanvas<'a> {
+    pixels:     &'a mut [u32],
+    width:      usize,
+    height:     usize,
+    /// Pixels from the start of one row to the next
+    stride:     usize,
+    format:     PixelFormat,
+    scale:      f64,
+}
 
-    pub fn wl_shm_create_pool(&self) -> Result<(), Box<dyn Error>> {
-        let shm_pool = self.shm_pool.lock().unwrap();
-        let current_id = self.current_id.fetch_add(1, Ordering::Relaxed) + 1;
-        self.shmpool_id.store(current_id, Ordering::Relaxed);
+impl<'a> ShmCanvas<'a> {
+    pub fn new(shm_pool: &'a mut ShmPool, buffer: &WlBuffer, scale: f64) -> Result<Self, OutOfBoundsErr> {
+        let stride = buffer.width;
+        let pixels = shm_pool.pixels_mut()
+            .get_mut(buffer.offset..buffer.offset + stride * buffer.height)
+            .ok_or(OutOfBoundsErr(format!("Buffer {} in its pool", buffer.id)))?;
+        Ok(ShmCanvas {
+            pixels,
+            width:  buffer.width,
+            height: buffer.height,
+            stride,
+            format: buffer.format,
+            scale,
+        })
+    }
 
-        let object = self.shm_id.load(Ordering::Relaxed);
-        if object == 0 {
-            return Err(UnsetErr("shm_id".to_string()).into());
-        }
-        const OPCODE: u16 = 0;
-        const REQ_SIZE: u16 = 16;
-        let id          = self.shmpool_id.load(Ordering::Relaxed);
-        let fds         = [shm_pool.fd];
-        let shm_size    = shm_pool.size;
-
-        let mut request = vec![0u8; REQ_SIZE as usize];
-        let mut offset: usize = 0;
-
-        // Request header
-        request.write_u32(&object,          &mut offset);
-        request.write_u16(&OPCODE,          &mut offset);
-        request.write_u16(&REQ_SIZE,        &mut offset);
-
-        // Id, size of shm pool
-        request.write_u32(&id,                  &mut offset);
-        request.write_u32(&(shm_size as u32),   &mut offset);
-        
-        let mut ancillary_buf = [0u8; 32];
-        let mut ancillary = SocketAncillary::new(&mut ancillary_buf[..]);
-        
-        if !ancillary.add_fds(&fds[..]) {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Failed to add FDs to ancillary data",
-            ).into());
+    fn index(&self, x: usize, y: usize) -> Result<usize, OutOfBoundsErr> {
+        if x >= self.width || y >= self.height {
+            return Err(OutOfBoundsErr(format!("Pixel {},{}", x, y)));
         }
+        Ok(y * self.stride + x)
+    }
+}
+
+impl Canvas for ShmCanvas<'_> {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    fn get(&self, x: usize, y: usize) -> Result<u32, OutOfBoundsErr> {
+        Ok(self.format.decode(self.pixels[self.index(x, y)?]))
+    }
 
-        self.socket.lock().unwrap().send_vectored_with_ancillary(&[std::io::IoSlice::new(&request)], &mut ancillary)?;
+    fn set(&mut self, x: usize, y: usize, color: u32) -> Result<(), OutOfBoundsErr> {
+        let index = self.index(x, y)?;
+        self.pixels[index] = self.format.encode(color);
+        Ok(())
+    }
+}
 
+impl WlClient {
+    pub fn wl_shm_event(&self, event: wl_shm::Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            wl_shm::Event::Format { format } => self.shm_formats.lock().unwrap().push(format),
+        }
         Ok(())
     }
 
-    pub fn wl_shm_pool_create_buffer(
-        &self,
-        buffer: &wl_buffer
-    ) -> Result<(), Box<dyn Error>> {
-        let object: u32 = self.shmpool_id.load(Ordering::Relaxed);
-        const REQ_SIZE: u16 = 32;
-        const OPCODE: u16 = 0;
+    /// Format for new buffers, without alpha when the background is opaque anyway
+    pub fn shm_format(&self) -> PixelFormat {
+        let formats = self.shm_formats.lock().unwrap();
+        let supported = |format: wl_shm::Format| formats.contains(&(format as u32));
+        if self.config.background >> 24 != 0xff {
+            PixelFormat::Argb8888
+        } else if self.config.deep_color && supported(wl_shm::Format::Xrgb2101010) {
+            PixelFormat::Xrgb2101010
+        } else if supported(wl_shm::Format::Xrgb8888) {
+            PixelFormat::Xrgb8888
+        } else {
+            PixelFormat::Argb8888
+        }
+    }
 
-        let byte_offset: u32 = buffer.offset as u32 * 4;
-        let width: u32 = buffer.width as u32;
-        let height: u32 = buffer.height as u32;
-        let stride: u32 = width * 4;
-        let id = buffer.id;
-        let format = 0;
+    pub fn wl_shm_create_pool(&self, shm_pool: &ShmPool) -> Result<u32, Box<dyn Error>> {
+        let object = self.global(&wl_shm::INTERFACE)?;
 
-        let mut offset: usize = 0;
-        let mut request = vec![0u8; REQ_SIZE as usize];
+        // The request owns a duplicate of the pool fd until it is sent
+        let fd = shm_pool.fd.try_clone()?;
 
-        request.write_u32(&object, &mut offset);
-        request.write_u16(&OPCODE, &mut offset);
-        request.write_u16(&REQ_SIZE, &mut offset);
+        let id = self.new_object_ignored(&wl_shm_pool::INTERFACE);
+        self.send(object, wl_shm::Request::CreatePool {
+            id,
+            fd,
+            size:   shm_pool.size as i32,
+        })?;
 
-        request.write_u32(&id,          &mut offset);
-        request.write_u32(&byte_offset, &mut offset);
-        request.write_u32(&width,       &mut offset);
-        request.write_u32(&height,      &mut offset);
-        request.write_u32(&stride,      &mut offset);
-        request.write_u32(&format,      &mut offset);
+        Ok(id)
+    }
 
-        self.socket.lock().unwrap().write(&request)?;
+    pub fn wl_shm_pool_create_buffer(
+        &self,
+        wl_shm_pool: u32,
+        buffer: &WlBuffer
+    ) -> Result<(), Box<dyn Error>> {
+        self.send(wl_shm_pool, wl_shm_pool::Request::CreateBuffer {
+            id:     buffer.id,
+            offset: buffer.offset as i32 * 4,
+            width:  buffer.width as i32,
+            height: buffer.height as i32,
+            stride: buffer.width as i32 * 4,
+            format: match buffer.format {
+                PixelFormat::Argb8888 => wl_shm::Format::Argb8888,
+                PixelFormat::Xrgb8888 => wl_shm::Format::Xrgb8888,
+                PixelFormat::Xrgb2101010 => wl_shm::Format::Xrgb2101010,
+            } as u32,
+        })
+    }
 
-        Ok(())
+    /// Tell the compositor the pool grew, it can never shrink
+    pub fn wl_shm_pool_resize(&self, wl_shm_pool: u32, shm_pool: &ShmPool) -> Result<(), Box<dyn Error>> {
+        self.send(wl_shm_pool, wl_shm_pool::Request::Resize { size: shm_pool.size as i32 })
     }
 }
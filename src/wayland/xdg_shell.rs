@@ -0,0 +1,83 @@
+use std::error::Error;
+
+use crate::{config::{DEFAULT_HEIGHT, DEFAULT_WIDTH}, wayland::{protocol::{xdg_surface, xdg_toplevel, xdg_wm_base}, surface::{Role, UnsetErr}, wl_client::WlClient}};
+
+const TITLE: &str = "ChloroStart";
+
+impl WlClient {
+    pub fn xdg_wm_base_event(&self, xdg_wm_base: u32, event: xdg_wm_base::Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            xdg_wm_base::Event::Ping { serial } => self.xdg_wm_base_pong(xdg_wm_base, serial),
+        }
+    }
+
+    pub fn xdg_wm_base_pong(&self, xdg_wm_base: u32, serial: u32) -> Result<(), Box<dyn Error>> {
+        self.send(xdg_wm_base, xdg_wm_base::Request::Pong { serial })
+    }
+
+    /// Make wl_surface a regular window, for compositors without layer shell
+    ///
+    /// The namespace doubles as app id, min and max size pin the window to the
+    /// configured size so tiling compositors float it
+    pub fn init_xdg_toplevel(&self, wl_surface: u32) -> Result<Role, Box<dyn Error>> {
+        let xdg_surface = self.xdg_wm_base_get_xdg_surface(wl_surface)?;
+        let xdg_toplevel = self.new_object(move |client, _, event| client.xdg_toplevel_event(wl_surface, event));
+        self.send(xdg_surface, xdg_surface::Request::GetToplevel { id: xdg_toplevel })?;
+
+        let placement = &self.config.placement;
+        self.send(xdg_toplevel, xdg_toplevel::Request::SetTitle { title: TITLE.to_string() })?;
+        self.send(xdg_toplevel, xdg_toplevel::Request::SetAppId { app_id: placement.namespace.clone() })?;
+        // Zero leaves that side unconstrained, like a stretched layer surface
+        let (width, height) = (placement.width as i32, placement.height as i32);
+        self.send(xdg_toplevel, xdg_toplevel::Request::SetMinSize { width, height })?;
+        self.send(xdg_toplevel, xdg_toplevel::Request::SetMaxSize { width, height })?;
+
+        Ok(Role::Toplevel { xdg_surface, xdg_toplevel })
+    }
+
+    pub fn xdg_wm_base_get_xdg_surface(&self, wl_surface: u32) -> Result<u32, Box<dyn Error>> {
+        let object = self.global(&xdg_wm_base::INTERFACE)?;
+
+        let id = self.new_object(move |client, id, event| client.xdg_surface_event(wl_surface, id, event));
+        self.send(object, xdg_wm_base::Request::GetXdgSurface { id, surface: wl_surface })?;
+
+        Ok(id)
+    }
+
+    pub fn xdg_surface_event(&self, wl_surface: u32, xdg_surface: u32, event: xdg_surface::Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            xdg_surface::Event::Configure { serial } => self.xdg_surface_configure(wl_surface, xdg_surface, serial),
+        }
+    }
+
+    /// Apply the size the toplevel configure before it asked for
+    pub fn xdg_surface_configure(&self, wl_surface: u32, xdg_surface: u32, serial: u32) -> Result<(), Box<dyn Error>> {
+        let (width, height) = self.surfaces.lock().unwrap().iter()
+            .find(|surface| surface.wl_surface == wl_surface)
+            .ok_or(UnsetErr("surface".to_string()))?
+            .pending_size;
+
+        // Zero leaves the size up to us, a stretched size has no meaning for a window
+        let placement = &self.config.placement;
+        let width = [width, placement.width, DEFAULT_WIDTH].into_iter().find(|width| *width != 0).unwrap();
+        let height = [height, placement.height, DEFAULT_HEIGHT].into_iter().find(|height| *height != 0).unwrap();
+        self.surface_resize(wl_surface, width as usize, height as usize)?;
+
+        self.send(xdg_surface, xdg_surface::Request::AckConfigure { serial })?;
+        self.wl_surface_frame(wl_surface)
+    }
+
+    pub fn xdg_toplevel_event(&self, wl_surface: u32, event: xdg_toplevel::Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            xdg_toplevel::Event::Configure { width, height, .. } => {
+                println!("Configure toplevel:\n\twidth: {}\n\theight: {}", width, height);
+                if let Some(surface) = self.surfaces.lock().unwrap().iter_mut().find(|surface| surface.wl_surface == wl_surface) {
+                    surface.pending_size = (width.max(0) as u32, height.max(0) as u32);
+                }
+                Ok(())
+            }
+            xdg_toplevel::Event::Close => self.exit(),
+            xdg_toplevel::Event::ConfigureBounds { .. } | xdg_toplevel::Event::WmCapabilities { .. } => Ok(()),
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use core::str;
-use std::{collections::HashMap, fmt::{Debug, Display}, string::{self, ParseError}};
+use std::{collections::HashMap, fmt::Debug};
 
-use super::shm::ShmPool;
+use super::shm::ShmMap;
 
 enum Data<'a> {
     Block(&'a str, Vec<Data<'a>>),
@@ -17,24 +17,24 @@ impl Debug for Data<'_> {
 }
 use Data::*;
 
-fn parse_block(map: &str) -> Vec<Data> {
+fn parse_block(map: &str) -> Vec<Data<'_>> {
     let mut begin = 0;
     let mut stack = 0;
     let mut is_statement = true;
     let mut datas = Vec::new();
     for (i, byte) in map.bytes().enumerate() {
         if stack == 0 {
-            if byte == '{' as u8 {
+            if byte == b'{' {
                 datas.push(Block(
                     map.get(begin..i).unwrap(),
                     parse_block(map.get(i+1..).unwrap())
                 ));
                 is_statement = false;
             }
-            else if byte == '}' as u8 {
+            else if byte == b'}' {
                 return datas;
             }
-            else if byte == ';' as u8 || byte == ',' as u8 || byte == ']' as u8 {
+            else if byte == b';' || byte == b',' || byte == b']' {
                 if is_statement {
                     datas.push(Statement(map.get(begin..i).unwrap()));
                 } else {
@@ -42,15 +42,15 @@ fn parse_block(map: &str) -> Vec<Data> {
                 }
                 begin = i+1;
             }
-            else if byte == '[' as u8 {
+            else if byte == b'[' {
                 begin = i+1;
             }
         }
 
-        if byte == '{' as u8 {
+        if byte == b'{' {
             stack += 1;
         }
-        else if byte == '}' as u8 {
+        else if byte == b'}' {
             stack -= 1;
         }
     };
@@ -60,14 +60,12 @@ fn parse_block(map: &str) -> Vec<Data> {
 
 fn xkb_symbols(xkb_map: &Data) -> Result<HashMap<String, Vec<String>>, ()> {
     let mut map_symbols: Option<&Vec<Data>> = None;
-    if let Block(header, subdata) = xkb_map {
-        if header.find("xkb_keymap").is_some() {
-            for subdata in subdata {
-                if let Block(header, subdata) = subdata {
-                    if header.find("xkb_symbols").is_some() {
-                        map_symbols = Some(&subdata);
-                    }
-                }
+    if let Block(header, subdata) = xkb_map
+        && header.contains("xkb_keymap") {
+        for subdata in subdata {
+            if let Block(header, subdata) = subdata
+                && header.contains("xkb_symbols") {
+                map_symbols = Some(subdata);
             }
         }
     }
@@ -75,26 +73,25 @@ fn xkb_symbols(xkb_map: &Data) -> Result<HashMap<String, Vec<String>>, ()> {
     let map_symbols: &Vec<Data> = map_symbols.ok_or(())?;
     let mut res = HashMap::new();
     for map_symbols in map_symbols {
-        if let Block(header, map_symbols) = map_symbols {
-            if header.find("key").is_some() {
-                let keysym = header.get(
-                    header.find('<').ok_or(())?+1..
-                    header.find('>').ok_or(())?
-                ).ok_or(())?.to_string();
-                let mut symbols = Vec::new();
-                for map_symbol in map_symbols {
-                    if let Statement(map_symbol) = &map_symbol {
-                        let mut symbol = String::new();
-                        for ch in map_symbol.chars() {
-                            if !ch.is_whitespace() {
-                                symbol.push(ch);
-                            }
+        if let Block(header, map_symbols) = map_symbols
+            && header.contains("key") {
+            let keysym = header.get(
+                header.find('<').ok_or(())?+1..
+                header.find('>').ok_or(())?
+            ).ok_or(())?.to_string();
+            let mut symbols = Vec::new();
+            for map_symbol in map_symbols {
+                if let Statement(map_symbol) = &map_symbol {
+                    let mut symbol = String::new();
+                    for ch in map_symbol.chars() {
+                        if !ch.is_whitespace() {
+                            symbol.push(ch);
                         }
-                        symbols.push(symbol);
                     }
+                    symbols.push(symbol);
                 }
-                res.insert(keysym, symbols);
             }
+            res.insert(keysym, symbols);
         }
     }
 
@@ -102,24 +99,17 @@ fn xkb_symbols(xkb_map: &Data) -> Result<HashMap<String, Vec<String>>, ()> {
 }
 
 fn first_non_whitespace(s: &str) -> Option<char> {
-    for ch in s.chars() {
-        if !ch.is_whitespace() {
-            return Some(ch);
-        }
-    }
-    return None;
+    s.chars().find(|ch| !ch.is_whitespace())
 }
 
 fn xkb_keycodes(xkb_map: &Data) -> Result<HashMap<u32, String>, ()> {
     let mut keycodes: Option<&Vec<Data>> = None;
-    if let Block(header, subdata) = xkb_map {
-        if header.find("xkb_keymap").is_some() {
-            for subdata in subdata {
-                if let Block(header, subdata) = subdata {
-                    if header.find("xkb_keycodes").is_some() {
-                        keycodes = Some(&subdata);
-                    }
-                }
+    if let Block(header, subdata) = xkb_map
+        && header.contains("xkb_keymap") {
+        for subdata in subdata {
+            if let Block(header, subdata) = subdata
+                && header.contains("xkb_keycodes") {
+                keycodes = Some(subdata);
             }
         }
     }
@@ -137,7 +127,7 @@ fn xkb_keycodes(xkb_map: &Data) -> Result<HashMap<u32, String>, ()> {
             ).ok_or(())?;
             let mut keycode = String::new();
             for ch in map_keycode.get(map_keycode.find('=').ok_or(())?..).ok_or(())?.chars() {
-                if ch.is_digit(10) {
+                if ch.is_ascii_digit() {
                     keycode.push(ch);
                 }
             }
@@ -150,8 +140,8 @@ fn xkb_keycodes(xkb_map: &Data) -> Result<HashMap<u32, String>, ()> {
     Ok(res)
 }
 
-pub fn gen_id_keysym_mapping(xkb_map: &ShmPool) -> Option<HashMap<u32, Vec<String>>> {
-    let xkb_map = unsafe {str::from_raw_parts(xkb_map.addr as *const u8, xkb_map.size)};
+pub fn gen_id_keysym_mapping(xkb_map: &ShmMap) -> Option<HashMap<u32, Vec<String>>> {
+    let xkb_map = str::from_utf8(xkb_map.bytes()).ok()?;
     let mut res = HashMap::new();
     let data = parse_block(xkb_map);
     let mut symbols = xkb_symbols(&data[0]).ok()?;